@@ -220,11 +220,17 @@ pub enum AmmInstruction {
 
 impl AmmInstruction {
     /// Unpacks a byte buffer into a [AmmInstruction](enum.AmmInstruction.html).
+    #[allow(deprecated)]
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
         let (&tag, rest) = input
             .split_first()
             .ok_or(ProgramError::InvalidInstructionData)?;
         Ok(match tag {
+            0 => {
+                let (nonce, rest) = Self::unpack_u8(rest)?;
+                let (open_time, _rest) = Self::unpack_u64(rest)?;
+                Self::Initialize(InitializeInstruction { nonce, open_time })
+            }
             1 => {
                 let (nonce, rest) = Self::unpack_u8(rest)?;
                 let (open_time, rest) = Self::unpack_u64(rest)?;
@@ -261,6 +267,10 @@ impl AmmInstruction {
                     minimum_amount_out,
                 })
             }
+            10 => {
+                let (nonce, _rest) = Self::unpack_u8(rest)?;
+                Self::PreInitialize(PreInitializeInstruction { nonce })
+            }
             11 => {
                 let (max_amount_in, rest) = Self::unpack_u64(rest)?;
                 let (amount_out, _rest) = Self::unpack_u64(rest)?;
@@ -270,12 +280,12 @@ impl AmmInstruction {
                 })
             }
 
-            _ => return Err(ProgramError::InvalidInstructionData.into()),
+            _ => return Err(ProgramError::InvalidInstructionData),
         })
     }
 
     fn unpack_u8(input: &[u8]) -> Result<(u8, &[u8]), ProgramError> {
-        if input.len() >= 1 {
+        if !input.is_empty() {
             let (amount, rest) = input.split_at(1);
             let amount = amount
                 .get(..1)
@@ -284,7 +294,7 @@ impl AmmInstruction {
                 .ok_or(ProgramError::InvalidInstructionData)?;
             Ok((amount, rest))
         } else {
-            Err(ProgramError::InvalidInstructionData.into())
+            Err(ProgramError::InvalidInstructionData)
         }
     }
 
@@ -298,14 +308,22 @@ impl AmmInstruction {
                 .ok_or(ProgramError::InvalidInstructionData)?;
             Ok((amount, rest))
         } else {
-            Err(ProgramError::InvalidInstructionData.into())
+            Err(ProgramError::InvalidInstructionData)
         }
     }
 
     /// Packs a [AmmInstruction](enum.AmmInstruction.html) into a byte buffer.
+    ///
+    /// Reserved variants have no known layout and fail with `InvalidInstructionData`.
+    #[allow(deprecated)]
     pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
         let mut buf = Vec::with_capacity(size_of::<Self>());
-        match &*self {
+        match self {
+            Self::Initialize(InitializeInstruction { nonce, open_time }) => {
+                buf.push(0);
+                buf.push(*nonce);
+                buf.extend_from_slice(&open_time.to_le_bytes());
+            }
             Self::Initialize2(InitializeInstruction2 {
                 nonce,
                 open_time,
@@ -341,6 +359,10 @@ impl AmmInstruction {
                 buf.extend_from_slice(&amount_in.to_le_bytes());
                 buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
             }
+            Self::PreInitialize(PreInitializeInstruction { nonce }) => {
+                buf.push(10);
+                buf.push(*nonce);
+            }
             Self::SwapBaseOut(SwapInstructionBaseOut {
                 max_amount_in,
                 amount_out,
@@ -349,7 +371,12 @@ impl AmmInstruction {
                 buf.extend_from_slice(&max_amount_in.to_le_bytes());
                 buf.extend_from_slice(&amount_out.to_le_bytes());
             }
-            _ => {}
+            Self::Reserved0
+            | Self::Reserved1
+            | Self::Reserved2
+            | Self::Reserved3
+            | Self::Reserved4
+            | Self::Reserved5 => return Err(ProgramError::InvalidInstructionData),
         }
         Ok(buf)
     }
@@ -666,4 +693,102 @@ pub fn swap_base_out(
         accounts,
         data,
     })
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[allow(deprecated)]
+mod tests {
+    use super::*;
+
+    fn round_trip(instruction: AmmInstruction) {
+        let packed = instruction.pack().unwrap();
+        assert_eq!(AmmInstruction::unpack(&packed).unwrap(), instruction);
+    }
+
+    #[test]
+    fn initialize_round_trip() {
+        round_trip(AmmInstruction::Initialize(InitializeInstruction {
+            nonce: 254,
+            open_time: 1_700_000_000,
+        }));
+    }
+
+    #[test]
+    fn initialize2_round_trip() {
+        round_trip(AmmInstruction::Initialize2(InitializeInstruction2 {
+            nonce: 255,
+            open_time: 1_700_000_000,
+            init_pc_amount: 5_000_000_000,
+            init_coin_amount: u64::MAX,
+        }));
+    }
+
+    #[test]
+    fn deposit_round_trip() {
+        round_trip(AmmInstruction::Deposit(DepositInstruction {
+            max_coin_amount: 1_000,
+            max_pc_amount: 2_000,
+            base_side: 1,
+        }));
+    }
+
+    #[test]
+    fn withdraw_round_trip() {
+        round_trip(AmmInstruction::Withdraw(WithdrawInstruction { amount: 42 }));
+    }
+
+    #[test]
+    fn swap_base_in_round_trip() {
+        round_trip(AmmInstruction::SwapBaseIn(SwapInstructionBaseIn {
+            amount_in: 1_000_000,
+            minimum_amount_out: 990_000,
+        }));
+    }
+
+    #[test]
+    fn pre_initialize_round_trip() {
+        round_trip(AmmInstruction::PreInitialize(PreInitializeInstruction { nonce: 7 }));
+    }
+
+    #[test]
+    fn swap_base_out_round_trip() {
+        round_trip(AmmInstruction::SwapBaseOut(SwapInstructionBaseOut {
+            max_amount_in: 1_010_000,
+            amount_out: 1_000_000,
+        }));
+    }
+
+    #[test]
+    fn reserved_variants_are_rejected() {
+        for instruction in [
+            AmmInstruction::Reserved0,
+            AmmInstruction::Reserved1,
+            AmmInstruction::Reserved2,
+            AmmInstruction::Reserved3,
+            AmmInstruction::Reserved4,
+            AmmInstruction::Reserved5,
+        ] {
+            assert_eq!(instruction.pack(), Err(ProgramError::InvalidInstructionData));
+        }
+        for tag in [2u8, 5, 6, 7, 8, 12, 13] {
+            assert_eq!(
+                AmmInstruction::unpack(&[tag; 33]),
+                Err(ProgramError::InvalidInstructionData)
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_data_is_rejected() {
+        let packed = AmmInstruction::SwapBaseIn(SwapInstructionBaseIn {
+            amount_in: 1,
+            minimum_amount_out: 1,
+        })
+        .pack()
+        .unwrap();
+        assert_eq!(
+            AmmInstruction::unpack(&packed[..packed.len() - 1]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        assert_eq!(AmmInstruction::unpack(&[]), Err(ProgramError::InvalidInstructionData));
+    }
+}
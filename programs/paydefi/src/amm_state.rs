@@ -0,0 +1,219 @@
+//! Pool state of the Raydium AMM program
+
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+use std::convert::TryInto;
+
+/// Size of the `AmmInfo` account.
+pub const AMM_INFO_LEN: usize = 752;
+
+const FEES_OFFSET: usize = 128;
+const STATE_DATA_OFFSET: usize = 192;
+const COIN_VAULT_OFFSET: usize = 336;
+const AMM_OWNER_OFFSET: usize = 688;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Fees {
+    /// numerator of the min_separate
+    pub min_separate_numerator: u64,
+    /// denominator of the min_separate
+    pub min_separate_denominator: u64,
+    /// numerator of the fee
+    pub trade_fee_numerator: u64,
+    /// denominator of the fee
+    /// and 'trade_fee_denominator' must be equal to 'min_separate_denominator'
+    pub trade_fee_denominator: u64,
+    /// numerator of the pnl
+    pub pnl_numerator: u64,
+    /// denominator of the pnl
+    pub pnl_denominator: u64,
+    /// numerator of the swap_fee
+    pub swap_fee_numerator: u64,
+    /// denominator of the swap_fee
+    pub swap_fee_denominator: u64,
+}
+
+/// Fields of the `AmmInfo` account used to locate the pool and quote swaps.
+///
+/// Funds in orders resting on the market are not included, so quotes are exact for
+/// pools whose orderbook is disabled and approximate otherwise.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AmmInfo {
+    pub status: u64,
+    pub nonce: u64,
+    pub coin_decimals: u64,
+    pub pc_decimals: u64,
+    pub fees: Fees,
+    /// coin amount owed to the pnl owner, still held in the coin vault
+    pub need_take_pnl_coin: u64,
+    /// pc amount owed to the pnl owner, still held in the pc vault
+    pub need_take_pnl_pc: u64,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub coin_vault_mint: Pubkey,
+    pub pc_vault_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub open_orders: Pubkey,
+    pub market: Pubkey,
+    pub market_program: Pubkey,
+    pub target_orders: Pubkey,
+    pub amm_owner: Pubkey,
+    pub lp_amount: u64,
+}
+
+impl AmmInfo {
+    /// Unpacks the data of an `AmmInfo` account.
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != AMM_INFO_LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let fees = &input[FEES_OFFSET..STATE_DATA_OFFSET];
+        let keys = &input[COIN_VAULT_OFFSET..];
+        Ok(Self {
+            status: read_u64(input, 0),
+            nonce: read_u64(input, 1),
+            coin_decimals: read_u64(input, 4),
+            pc_decimals: read_u64(input, 5),
+            fees: Fees {
+                min_separate_numerator: read_u64(fees, 0),
+                min_separate_denominator: read_u64(fees, 1),
+                trade_fee_numerator: read_u64(fees, 2),
+                trade_fee_denominator: read_u64(fees, 3),
+                pnl_numerator: read_u64(fees, 4),
+                pnl_denominator: read_u64(fees, 5),
+                swap_fee_numerator: read_u64(fees, 6),
+                swap_fee_denominator: read_u64(fees, 7),
+            },
+            need_take_pnl_coin: read_u64(&input[STATE_DATA_OFFSET..], 0),
+            need_take_pnl_pc: read_u64(&input[STATE_DATA_OFFSET..], 1),
+            coin_vault: read_pubkey(keys, 0),
+            pc_vault: read_pubkey(keys, 1),
+            coin_vault_mint: read_pubkey(keys, 2),
+            pc_vault_mint: read_pubkey(keys, 3),
+            lp_mint: read_pubkey(keys, 4),
+            open_orders: read_pubkey(keys, 5),
+            market: read_pubkey(keys, 6),
+            market_program: read_pubkey(keys, 7),
+            target_orders: read_pubkey(keys, 8),
+            amm_owner: read_pubkey(&input[AMM_OWNER_OFFSET..], 0),
+            lp_amount: read_u64(&input[AMM_OWNER_OFFSET + 32..], 0),
+        })
+    }
+
+    /// Pool amounts available to swaps, the vault balances less the pnl owed.
+    pub fn pool_amounts(&self, coin_vault_amount: u64, pc_vault_amount: u64) -> Result<(u64, u64), ProgramError> {
+        let coin = coin_vault_amount
+            .checked_sub(self.need_take_pnl_coin)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        let pc = pc_vault_amount
+            .checked_sub(self.need_take_pnl_pc)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        Ok((coin, pc))
+    }
+
+    /// Expected output of a `swap_base_in` of `amount_in`, after the swap fee.
+    ///
+    /// Mirrors the pool's own rounding: the fee is rounded up and the output down.
+    pub fn swap_base_in_amount_out(
+        &self,
+        coin_vault_amount: u64,
+        pc_vault_amount: u64,
+        amount_in: u64,
+        coin_to_pc: bool,
+    ) -> Result<u64, ProgramError> {
+        let (coin, pc) = self.pool_amounts(coin_vault_amount, pc_vault_amount)?;
+        let numerator = self.fees.swap_fee_numerator as u128;
+        let denominator = self.fees.swap_fee_denominator as u128;
+        if denominator == 0 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let amount_in = amount_in as u128;
+        let swap_fee = (amount_in * numerator).div_ceil(denominator);
+        let amount_in_after_fee = amount_in
+            .checked_sub(swap_fee)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+
+        let (reserve_in, reserve_out) = if coin_to_pc {
+            (coin as u128, pc as u128)
+        } else {
+            (pc as u128, coin as u128)
+        };
+        let amount_out = (reserve_out * amount_in_after_fee)
+            .checked_div(reserve_in + amount_in_after_fee)
+            .unwrap_or(0);
+        u64::try_from(amount_out).map_err(|_| ProgramError::ArithmeticOverflow)
+    }
+}
+
+fn read_u64(data: &[u8], index: usize) -> u64 {
+    u64::from_le_bytes(data[index * 8..index * 8 + 8].try_into().unwrap())
+}
+
+fn read_pubkey(data: &[u8], index: usize) -> Pubkey {
+    Pubkey::new_from_array(data[index * 32..index * 32 + 32].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_u64(data: &mut [u8], offset: usize, value: u64) {
+        data[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn pool(need_take_pnl_coin: u64, need_take_pnl_pc: u64) -> AmmInfo {
+        let mut data = vec![0u8; AMM_INFO_LEN];
+        write_u64(&mut data, 0, 6);
+        write_u64(&mut data, FEES_OFFSET + 6 * 8, 25);
+        write_u64(&mut data, FEES_OFFSET + 7 * 8, 10_000);
+        write_u64(&mut data, STATE_DATA_OFFSET, need_take_pnl_coin);
+        write_u64(&mut data, STATE_DATA_OFFSET + 8, need_take_pnl_pc);
+        data[COIN_VAULT_OFFSET..COIN_VAULT_OFFSET + 32].copy_from_slice(&[1; 32]);
+        data[COIN_VAULT_OFFSET + 32..COIN_VAULT_OFFSET + 64].copy_from_slice(&[2; 32]);
+        data[AMM_OWNER_OFFSET..AMM_OWNER_OFFSET + 32].copy_from_slice(&[3; 32]);
+        AmmInfo::unpack(&data).unwrap()
+    }
+
+    #[test]
+    fn unpack_reads_layout() {
+        let info = pool(7, 8);
+        assert_eq!(info.status, 6);
+        assert_eq!(info.fees.swap_fee_numerator, 25);
+        assert_eq!(info.fees.swap_fee_denominator, 10_000);
+        assert_eq!(info.need_take_pnl_coin, 7);
+        assert_eq!(info.need_take_pnl_pc, 8);
+        assert_eq!(info.coin_vault, Pubkey::new_from_array([1; 32]));
+        assert_eq!(info.pc_vault, Pubkey::new_from_array([2; 32]));
+        assert_eq!(info.amm_owner, Pubkey::new_from_array([3; 32]));
+    }
+
+    #[test]
+    fn unpack_rejects_wrong_length() {
+        assert_eq!(
+            AmmInfo::unpack(&[0; AMM_INFO_LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn quote_charges_fee_and_excludes_pnl() {
+        let info = pool(1_000, 0);
+        // 0.25% of 10_000 is 25, leaving 9_975 to swap against 1_000_000 / 2_000_000
+        assert_eq!(
+            info.swap_base_in_amount_out(1_001_000, 2_000_000, 10_000, true).unwrap(),
+            2_000_000 * 9_975 / 1_009_975
+        );
+        assert_eq!(
+            info.swap_base_in_amount_out(1_001_000, 2_000_000, 10_000, false).unwrap(),
+            1_000_000 * 9_975 / 2_009_975
+        );
+    }
+
+    #[test]
+    fn quote_rounds_fee_up() {
+        let info = pool(0, 0);
+        // 0.25% of 1 rounds up to 1, nothing is left to swap
+        assert_eq!(info.swap_base_in_amount_out(1_000, 1_000, 1, true).unwrap(), 0);
+    }
+}
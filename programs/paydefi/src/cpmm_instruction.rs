@@ -0,0 +1,252 @@
+//! Instruction types for the Raydium CPMM program
+
+#![allow(clippy::too_many_arguments)]
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::convert::TryInto;
+
+solana_program::declare_id!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C");
+
+/// Anchor discriminator of `swap_base_input`, `sha256("global:swap_base_input")[..8]`.
+pub const SWAP_BASE_INPUT_DISCRIMINATOR: [u8; 8] = [143, 190, 90, 218, 196, 30, 51, 222];
+/// Anchor discriminator of `swap_base_output`, `sha256("global:swap_base_output")[..8]`.
+pub const SWAP_BASE_OUTPUT_DISCRIMINATOR: [u8; 8] = [55, 217, 98, 86, 163, 74, 180, 173];
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapBaseInputInstruction {
+    // SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+    pub amount_in: u64,
+    /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+    pub minimum_amount_out: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapBaseOutputInstruction {
+    /// Maximum amount of SOURCE token to spend, prevents excessive slippage
+    pub max_amount_in: u64,
+    // DESTINATION amount to receive
+    pub amount_out: u64,
+}
+
+/// Instructions of the CPMM program used for payments.
+///
+/// Both swaps take the same accounts:
+///
+///   0. `[signer]` User wallet Account
+///   1. `[]` $authority derived from `find_program_address(&[AUTH_SEED])`.
+///   2. `[]` AMM config Account
+///   3. `[writable]` Pool state Account
+///   4. `[writable]` User input token Account
+///   5. `[writable]` User output token Account
+///   6. `[writable]` Pool input vault Account
+///   7. `[writable]` Pool output vault Account
+///   8. `[]` Input token program id
+///   9. `[]` Output token program id
+///   10. `[]` Input token mint
+///   11. `[]` Output token mint
+///   12. `[writable]` Pool observation state Account
+#[derive(Clone, Debug, PartialEq)]
+pub enum CpmmInstruction {
+    /// Swap with an exact input amount and a slippage of minimum_amount_out
+    SwapBaseInput(SwapBaseInputInstruction),
+    /// Swap for an exact output amount with a slippage of max_amount_in
+    SwapBaseOutput(SwapBaseOutputInstruction),
+}
+
+impl CpmmInstruction {
+    /// Unpacks a byte buffer into a [CpmmInstruction](enum.CpmmInstruction.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < 8 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let (discriminator, rest) = input.split_at(8);
+        let (first, rest) = Self::unpack_u64(rest)?;
+        let (second, _rest) = Self::unpack_u64(rest)?;
+        match discriminator {
+            d if d == SWAP_BASE_INPUT_DISCRIMINATOR => Ok(Self::SwapBaseInput(SwapBaseInputInstruction {
+                amount_in: first,
+                minimum_amount_out: second,
+            })),
+            d if d == SWAP_BASE_OUTPUT_DISCRIMINATOR => Ok(Self::SwapBaseOutput(SwapBaseOutputInstruction {
+                max_amount_in: first,
+                amount_out: second,
+            })),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.len() >= 8 {
+            let (amount, rest) = input.split_at(8);
+            let amount = amount
+                .try_into()
+                .map(u64::from_le_bytes)
+                .map_err(|_| ProgramError::InvalidInstructionData)?;
+            Ok((amount, rest))
+        } else {
+            Err(ProgramError::InvalidInstructionData)
+        }
+    }
+
+    /// Packs a [CpmmInstruction](enum.CpmmInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Result<Vec<u8>, ProgramError> {
+        let mut buf = Vec::with_capacity(24);
+        match self {
+            Self::SwapBaseInput(SwapBaseInputInstruction {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.extend_from_slice(&SWAP_BASE_INPUT_DISCRIMINATOR);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::SwapBaseOutput(SwapBaseOutputInstruction {
+                max_amount_in,
+                amount_out,
+            }) => {
+                buf.extend_from_slice(&SWAP_BASE_OUTPUT_DISCRIMINATOR);
+                buf.extend_from_slice(&max_amount_in.to_le_bytes());
+                buf.extend_from_slice(&amount_out.to_le_bytes());
+            }
+        }
+        Ok(buf)
+    }
+}
+
+fn swap_accounts(
+    payer: &Pubkey,
+    authority: &Pubkey,
+    amm_config: &Pubkey,
+    pool_state: &Pubkey,
+    input_token_account: &Pubkey,
+    output_token_account: &Pubkey,
+    input_vault: &Pubkey,
+    output_vault: &Pubkey,
+    input_token_program: &Pubkey,
+    output_token_program: &Pubkey,
+    input_token_mint: &Pubkey,
+    output_token_mint: &Pubkey,
+    observation_state: &Pubkey,
+) -> Vec<AccountMeta> {
+    vec![
+        // user
+        AccountMeta::new_readonly(*payer, true),
+        // pool
+        AccountMeta::new_readonly(*authority, false),
+        AccountMeta::new_readonly(*amm_config, false),
+        AccountMeta::new(*pool_state, false),
+        // user
+        AccountMeta::new(*input_token_account, false),
+        AccountMeta::new(*output_token_account, false),
+        // pool
+        AccountMeta::new(*input_vault, false),
+        AccountMeta::new(*output_vault, false),
+        // token programs & mints
+        AccountMeta::new_readonly(*input_token_program, false),
+        AccountMeta::new_readonly(*output_token_program, false),
+        AccountMeta::new_readonly(*input_token_mint, false),
+        AccountMeta::new_readonly(*output_token_mint, false),
+        // oracle
+        AccountMeta::new(*observation_state, false),
+    ]
+}
+
+/// Creates a 'swap base input' instruction.
+pub fn swap_base_input(
+    cpmm_program: &Pubkey,
+    payer: &Pubkey,
+    authority: &Pubkey,
+    amm_config: &Pubkey,
+    pool_state: &Pubkey,
+    input_token_account: &Pubkey,
+    output_token_account: &Pubkey,
+    input_vault: &Pubkey,
+    output_vault: &Pubkey,
+    input_token_program: &Pubkey,
+    output_token_program: &Pubkey,
+    input_token_mint: &Pubkey,
+    output_token_mint: &Pubkey,
+    observation_state: &Pubkey,
+
+    amount_in: u64,
+    minimum_amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = CpmmInstruction::SwapBaseInput(SwapBaseInputInstruction {
+        amount_in,
+        minimum_amount_out,
+    })
+    .pack()?;
+
+    Ok(Instruction {
+        program_id: *cpmm_program,
+        accounts: swap_accounts(
+            payer,
+            authority,
+            amm_config,
+            pool_state,
+            input_token_account,
+            output_token_account,
+            input_vault,
+            output_vault,
+            input_token_program,
+            output_token_program,
+            input_token_mint,
+            output_token_mint,
+            observation_state,
+        ),
+        data,
+    })
+}
+
+/// Creates a 'swap base output' instruction.
+pub fn swap_base_output(
+    cpmm_program: &Pubkey,
+    payer: &Pubkey,
+    authority: &Pubkey,
+    amm_config: &Pubkey,
+    pool_state: &Pubkey,
+    input_token_account: &Pubkey,
+    output_token_account: &Pubkey,
+    input_vault: &Pubkey,
+    output_vault: &Pubkey,
+    input_token_program: &Pubkey,
+    output_token_program: &Pubkey,
+    input_token_mint: &Pubkey,
+    output_token_mint: &Pubkey,
+    observation_state: &Pubkey,
+
+    max_amount_in: u64,
+    amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = CpmmInstruction::SwapBaseOutput(SwapBaseOutputInstruction {
+        max_amount_in,
+        amount_out,
+    })
+    .pack()?;
+
+    Ok(Instruction {
+        program_id: *cpmm_program,
+        accounts: swap_accounts(
+            payer,
+            authority,
+            amm_config,
+            pool_state,
+            input_token_account,
+            output_token_account,
+            input_vault,
+            output_vault,
+            input_token_program,
+            output_token_program,
+            input_token_mint,
+            output_token_mint,
+            observation_state,
+        ),
+        data,
+    })
+}
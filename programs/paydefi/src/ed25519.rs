@@ -0,0 +1,76 @@
+//! Verification of Ed25519 precompile instructions
+
+use anchor_lang::prelude::*;
+use solana_program::{
+    ed25519_program,
+    instruction::Instruction,
+    sysvar::instructions::{load_current_index_checked, load_instruction_at_checked},
+};
+
+use crate::ErrorCode;
+
+/// Length of the `num_signatures` header plus padding byte.
+const HEADER_LEN: usize = 2;
+/// Length of one `Ed25519SignatureOffsets` entry.
+const OFFSETS_LEN: usize = 14;
+const PUBKEY_LEN: usize = 32;
+const SIGNATURE_LEN: usize = 64;
+
+/// Checks that the instruction preceding the current one is an Ed25519 precompile
+/// instruction verifying a single signature of `signer` over exactly `message`.
+///
+/// The precompile itself fails the transaction on a bad signature, so it is enough
+/// to ensure that it ran against the expected public key and message.
+pub fn verify_signature(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    let current_index = load_current_index_checked(instructions)?;
+    require!(current_index > 0, ErrorCode::MissingQuoteSignature);
+
+    let ix = load_instruction_at_checked(current_index as usize - 1, instructions)?;
+    verify_instruction(&ix, signer, message)
+}
+
+/// Checks that `ix` is an Ed25519 precompile instruction verifying a single signature
+/// of `signer` over exactly `message`.
+pub fn verify_instruction(ix: &Instruction, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    require_keys_eq!(ix.program_id, ed25519_program::ID, ErrorCode::MissingQuoteSignature);
+    require!(ix.accounts.is_empty(), ErrorCode::InvalidQuoteSignature);
+
+    let data = &ix.data;
+    require!(data.len() >= HEADER_LEN + OFFSETS_LEN, ErrorCode::InvalidQuoteSignature);
+    require!(data[0] == 1, ErrorCode::InvalidQuoteSignature);
+
+    let offsets = &data[HEADER_LEN..HEADER_LEN + OFFSETS_LEN];
+    let signature_offset = read_u16(offsets, 0);
+    let signature_ix_index = read_u16(offsets, 2);
+    let pubkey_offset = read_u16(offsets, 4);
+    let pubkey_ix_index = read_u16(offsets, 6);
+    let message_offset = read_u16(offsets, 8);
+    let message_size = read_u16(offsets, 10);
+    let message_ix_index = read_u16(offsets, 12);
+
+    // All data must live in the Ed25519 instruction itself
+    require!(
+        signature_ix_index == u16::MAX && pubkey_ix_index == u16::MAX && message_ix_index == u16::MAX,
+        ErrorCode::InvalidQuoteSignature
+    );
+    require!(
+        data.len() >= signature_offset as usize + SIGNATURE_LEN,
+        ErrorCode::InvalidQuoteSignature
+    );
+
+    let signed_pubkey = data
+        .get(pubkey_offset as usize..pubkey_offset as usize + PUBKEY_LEN)
+        .ok_or(ErrorCode::InvalidQuoteSignature)?;
+    require!(signed_pubkey == signer.as_ref(), ErrorCode::InvalidQuoteSignature);
+
+    let signed_message = data
+        .get(message_offset as usize..message_offset as usize + message_size as usize)
+        .ok_or(ErrorCode::InvalidQuoteSignature)?;
+    require!(signed_message == message, ErrorCode::InvalidQuoteSignature);
+
+    Ok(())
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
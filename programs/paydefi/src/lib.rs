@@ -1,8 +1,25 @@
 use anchor_lang::prelude::*;
-use anchor_spl::{associated_token::AssociatedToken, token::{Token, TokenAccount, Transfer as SplTransfer}};
+use anchor_lang::solana_program::sysvar::instructions as sysvar_instructions;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token::{spl_token::native_mint, Token, TokenAccount},
+    token_2022::{
+        self,
+        spl_token_2022::{
+            extension::{transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions},
+            state::Mint as MintState,
+        },
+    },
+    token_interface::{self, Mint, TokenInterface, TransferChecked},
+};
+use solana_program::{hash::hash, program_option::COption};
 use solana_security_txt::security_txt;
 
 pub mod amm_instruction;
+pub mod amm_state;
+pub mod cpmm_instruction;
+pub mod ed25519;
+pub mod whirlpool_instruction;
 
 declare_id!("Ea3YcYPZ7ZgGmxbyu31Fw5UQrv867c3AWXrKUGn6yupP");
 
@@ -17,17 +34,36 @@ security_txt! {
 }
 
 const FEE_DENOMINATOR: u32 = 10000;
+const MAX_FEE_SHARES: usize = 16;
+const MAX_INVOICE_MINTS: usize = 8;
+
+pub const RECEIPT_SEED: &[u8] = b"receipt";
+pub const CONFIG_SEED: &[u8] = b"config";
+pub const WSOL_SEED: &[u8] = b"wsol";
+pub const SWAP_PROGRAM_SEED: &[u8] = b"swap_program";
+pub const ESCROW_SEED: &[u8] = b"escrow";
+pub const ESCROW_VAULT_SEED: &[u8] = b"escrow_vault";
+pub const SUBSCRIPTION_SEED: &[u8] = b"subscription";
+pub const SPENDING_AUTHORIZATION_SEED: &[u8] = b"spending_authorization";
+pub const SPENDING_DELEGATE_SEED: &[u8] = b"spending_delegate";
+pub const INVOICE_SEED: &[u8] = b"invoice";
 
 #[program]
 pub mod paydefi {
     use amm_instruction::swap_base_in;
+    use amm_state::AmmInfo;
+    use anchor_lang::system_program;
     use anchor_spl::token;
-    use solana_program::program::invoke;
+    use solana_program::{instruction::{AccountMeta, Instruction}, program::invoke};
     use super::*;
 
     pub fn complete_transfer_payment(ctx: Context<CompleteTransferPayment>, payment: Payment) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
         let from_ata = &ctx.accounts.from_ata;
         let to_ata = &ctx.accounts.to_ata;
+        let mint = &ctx.accounts.mint;
         let token_program = &ctx.accounts.token_program;
         let payer = &ctx.accounts.payer;
         let treasury_ata = &ctx.accounts.treasury_ata;
@@ -37,26 +73,72 @@ pub mod paydefi {
             return Err(ErrorCode::PaymentExpired.into());
         }
 
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Ensure the quote does not pay out more than it pays in
+        let fee_amount = payment
+            .pay_in_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InvalidPaymentAmounts)?;
+
+        // Account for the mint's transfer fee so the merchant receives pay_out_amount
+        let fee_config = transfer_fee_config(mint)?;
+        let merchant_transfer_fee = inverse_transfer_fee(fee_config.as_ref(), payment.pay_out_amount)?;
+        require!(
+            merchant_transfer_fee == 0 || payment.gross_up_transfer_fee,
+            ErrorCode::TransferFeeNotAllowed
+        );
+        let merchant_amount = payment
+            .pay_out_amount
+            .checked_add(merchant_transfer_fee)
+            .ok_or(ErrorCode::TransferFeeOverflow)?;
+        let mut transfer_fee_withheld = merchant_transfer_fee;
+
         // Transfer fee to the treasury account if there is any fee
-        if payment.pay_in_amount > payment.pay_out_amount {
-            let fee_amount = payment.pay_in_amount - payment.pay_out_amount;
-            let cpi_accounts_fee = SplTransfer {
+        if fee_amount > 0 {
+            let cpi_accounts_fee = TransferChecked {
                 from: from_ata.to_account_info(),
+                mint: mint.to_account_info(),
                 to: treasury_ata.to_account_info(),
                 authority: payer.to_account_info(),
             };
             let cpi_context_fee = CpiContext::new(token_program.to_account_info(), cpi_accounts_fee);
-            token::transfer(cpi_context_fee, fee_amount)?;
+            token_interface::transfer_checked(cpi_context_fee, fee_amount, mint.decimals)?;
+            transfer_fee_withheld = transfer_fee_withheld
+                .checked_add(transfer_fee(fee_config.as_ref(), fee_amount)?)
+                .ok_or(ErrorCode::TransferFeeOverflow)?;
         }
 
         // Transfer tokens from payer to merchant
-        let cpi_accounts = SplTransfer {
+        let cpi_accounts = TransferChecked {
             from: from_ata.to_account_info(),
+            mint: mint.to_account_info(),
             to: to_ata.to_account_info(),
             authority: payer.to_account_info(),
         };
         let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_context, payment.pay_out_amount)?;
+        token_interface::transfer_checked(
+            cpi_context,
+            merchant_amount,
+            mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
 
         // Emit an event after the successful payment
         emit!(PaymentCompleted {
@@ -65,7 +147,8 @@ pub mod paydefi {
             pay_out_token: payment.pay_out_token,
             pay_in_amount: payment.pay_in_amount,
             pay_out_amount: payment.pay_out_amount,
-            fee_collected: payment.pay_in_amount - payment.pay_out_amount,
+            fee_collected: fee_amount,
+            transfer_fee_withheld,
             treasury: treasury_ata.key(),
             merchant: payment.merchant,
             payer: payer.key()
@@ -79,8 +162,12 @@ pub mod paydefi {
         payment: Payment,
         percentages: [u32; 8],
     ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
         let from_ata = &ctx.accounts.from_ata;
         let to_ata = &ctx.accounts.to_ata;
+        let mint = &ctx.accounts.mint;
         let token_program = &ctx.accounts.token_program;
         let payer = &ctx.accounts.payer;
 
@@ -89,6 +176,9 @@ pub mod paydefi {
             return Err(ErrorCode::PaymentExpired.into());
         }
 
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
         let receivers = [
             &ctx.accounts.receiver1,
             &ctx.accounts.receiver2,
@@ -99,50 +189,93 @@ pub mod paydefi {
             &ctx.accounts.receiver7,
             &ctx.accounts.receiver8,
         ];
+        let receiver_keys = receivers.map(|receiver| receiver.key());
+
+        // Ensure the payment, its percentages and receivers were quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &fee_percentages_message(&payment, &percentages, &receiver_keys)?,
+        )?;
+
+        // Ensure the quote does not pay out more than it pays in
+        let total_fee_amount = payment
+            .pay_in_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InvalidPaymentAmounts)?;
 
         let mut fee_received: [u64; 8] = [0; 8];
 
+        // Account for the mint's transfer fee so the merchant receives pay_out_amount
+        let fee_config = transfer_fee_config(mint)?;
+        let merchant_transfer_fee = inverse_transfer_fee(fee_config.as_ref(), payment.pay_out_amount)?;
+        require!(
+            merchant_transfer_fee == 0 || payment.gross_up_transfer_fee,
+            ErrorCode::TransferFeeNotAllowed
+        );
+        let merchant_amount = payment
+            .pay_out_amount
+            .checked_add(merchant_transfer_fee)
+            .ok_or(ErrorCode::TransferFeeOverflow)?;
+        let mut transfer_fee_withheld = merchant_transfer_fee;
+
         // Transfer fee to the treasury account if there is any fee
-        if payment.pay_in_amount > payment.pay_out_amount {
+        if total_fee_amount > 0 {
             // Check that the percentages sum up to 100 or less
             let total_percentage: u32 = percentages.iter().sum();
             require!(total_percentage == FEE_DENOMINATOR, ErrorCode::InvalidPercentage);
 
-            let total_fee_amount = payment.pay_in_amount - payment.pay_out_amount;
+            // Split the fee so that the shares add up to exactly the fee collected
+            fee_received.copy_from_slice(&split_fee(total_fee_amount, &percentages));
+
             // Distribute tokens to each receiver
             for i in 0..8 {
-                if percentages[i] > 0 {
-                    let fee_amount = (total_fee_amount as u64 * percentages[i] as u64 / FEE_DENOMINATOR as u64) as u64;
-
-                    // Skip if amount is zero
-                    if fee_amount == 0 {
-                        continue;
-                    }
+                let fee_amount = fee_received[i];
 
-                    fee_received[i] = fee_amount;
+                // Skip if amount is zero
+                if fee_amount == 0 {
+                    continue;
+                }
 
-                    // Transfer tokens to the receiver
-                    let receiver_ata = receivers[i];
+                // Transfer tokens to the receiver
+                let receiver_ata = receivers[i];
 
-                    let cpi_accounts_fee = SplTransfer {
-                        from: from_ata.to_account_info(),
-                        to: receiver_ata.to_account_info(),
-                        authority: payer.to_account_info(),
-                    };
-                    let cpi_context_fee = CpiContext::new(token_program.to_account_info(), cpi_accounts_fee);
-                    token::transfer(cpi_context_fee, fee_amount)?;
-                }
+                let cpi_accounts_fee = TransferChecked {
+                    from: from_ata.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: receiver_ata.to_account_info(),
+                    authority: payer.to_account_info(),
+                };
+                let cpi_context_fee = CpiContext::new(token_program.to_account_info(), cpi_accounts_fee);
+                token_interface::transfer_checked(cpi_context_fee, fee_amount, mint.decimals)?;
+                transfer_fee_withheld = transfer_fee_withheld
+                .checked_add(transfer_fee(fee_config.as_ref(), fee_amount)?)
+                .ok_or(ErrorCode::TransferFeeOverflow)?;
             }
         }
 
         // Transfer tokens from payer to merchant
-        let cpi_accounts = SplTransfer {
+        let cpi_accounts = TransferChecked {
             from: from_ata.to_account_info(),
+            mint: mint.to_account_info(),
             to: to_ata.to_account_info(),
             authority: payer.to_account_info(),
         };
         let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_context, payment.pay_out_amount)?;
+        token_interface::transfer_checked(
+            cpi_context,
+            merchant_amount,
+            mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            payer.key(),
+            total_fee_amount,
+            ctx.bumps.receipt,
+        )?;
 
         // Emit an event after the successful payment
         emit!(PaymentCompletedAndFeeDistributed {
@@ -151,7 +284,8 @@ pub mod paydefi {
             pay_out_token: payment.pay_out_token,
             pay_in_amount: payment.pay_in_amount,
             pay_out_amount: payment.pay_out_amount,
-            fee_collected: payment.pay_in_amount - payment.pay_out_amount,
+            fee_collected: total_fee_amount,
+            transfer_fee_withheld,
             fee_receiver1: receivers[0].key(),
             fee_receiver2: receivers[1].key(),
             fee_receiver3: receivers[2].key(),
@@ -175,7 +309,136 @@ pub mod paydefi {
         Ok(())
     }
 
-    pub fn complete_swap_payment(ctx: Context<CompletePayment>, payment: Payment) -> Result<()> {
+    pub fn complete_transfer_payment_with_fee_shares<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompleteTransferPaymentWithFeeShares<'info>>,
+        payment: Payment,
+        fee_shares: Vec<FeeShare>,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let from_ata = &ctx.accounts.from_ata;
+        let to_ata = &ctx.accounts.to_ata;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+        let payer = &ctx.accounts.payer;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment and its fee shares were quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &fee_shares_message(&payment, &fee_shares)?,
+        )?;
+
+        // Ensure there is exactly one receiver token account per fee share
+        require!(
+            fee_shares.len() <= MAX_FEE_SHARES && fee_shares.len() == ctx.remaining_accounts.len(),
+            ErrorCode::InvalidFeeShareCount
+        );
+
+        // Account for the mint's transfer fee so the merchant receives pay_out_amount
+        let fee_config = transfer_fee_config(mint)?;
+        let merchant_transfer_fee = inverse_transfer_fee(fee_config.as_ref(), payment.pay_out_amount)?;
+        require!(
+            merchant_transfer_fee == 0 || payment.gross_up_transfer_fee,
+            ErrorCode::TransferFeeNotAllowed
+        );
+        let merchant_amount = payment
+            .pay_out_amount
+            .checked_add(merchant_transfer_fee)
+            .ok_or(ErrorCode::TransferFeeOverflow)?;
+        let mut transfer_fee_withheld = merchant_transfer_fee;
+
+        // Split the fee so that the shares add up to exactly the fee collected
+        let total_fee_amount = payment.pay_in_amount.saturating_sub(payment.pay_out_amount);
+        let fee_amounts = split_fee_shares(total_fee_amount, &fee_shares)?;
+
+        // Distribute the fee to each receiver
+        let mut fee_received = Vec::with_capacity(fee_shares.len());
+        let receivers = fee_shares.iter().zip(ctx.remaining_accounts.iter()).zip(fee_amounts);
+        for ((share, receiver_info), fee_amount) in receivers {
+            let receiver_ata = InterfaceAccount::<token_interface::TokenAccount>::try_from(receiver_info)?;
+            require!(
+                receiver_ata.owner == share.receiver && receiver_ata.mint == payment.pay_in_token,
+                ErrorCode::InvalidFeeReceiver
+            );
+
+            if fee_amount > 0 {
+                let cpi_accounts_fee = TransferChecked {
+                    from: from_ata.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: receiver_info.clone(),
+                    authority: payer.to_account_info(),
+                };
+                let cpi_context_fee = CpiContext::new(token_program.to_account_info(), cpi_accounts_fee);
+                token_interface::transfer_checked(cpi_context_fee, fee_amount, mint.decimals)?;
+                transfer_fee_withheld = transfer_fee_withheld
+                .checked_add(transfer_fee(fee_config.as_ref(), fee_amount)?)
+                .ok_or(ErrorCode::TransferFeeOverflow)?;
+            }
+
+            fee_received.push(FeeShareReceived {
+                receiver: receiver_info.key(),
+                amount: fee_amount,
+            });
+        }
+
+        // Transfer tokens from payer to merchant
+        let cpi_accounts = TransferChecked {
+            from: from_ata.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to_ata.to_account_info(),
+            authority: payer.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(
+            cpi_context,
+            merchant_amount,
+            mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            payer.key(),
+            total_fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(PaymentCompletedAndFeeShared {
+            order_id: payment.order_id.clone(),
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: total_fee_amount,
+            transfer_fee_withheld,
+            fee_shares: fee_received,
+            merchant: payment.merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn complete_swap_payment(
+        ctx: Context<CompletePayment>,
+        payment: Payment,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
         let accts: &mut CompletePayment<'_> = ctx.accounts;
 
         let payer = &accts.payer;
@@ -186,6 +449,30 @@ pub mod paydefi {
         if Clock::get()?.unix_timestamp > payment.expiry {
             return Err(ErrorCode::PaymentExpired.into());
         }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Ensure the pool can cover the payment before swapping
+        require_keys_eq!(*accts.amm_id.owner, amm_instruction::ID, ErrorCode::InvalidAmmPool);
+        let amm_info = AmmInfo::unpack(&accts.amm_id.try_borrow_data()?)?;
+        require_keys_eq!(amm_info.coin_vault, accts.pool_coin_token_account.key(), ErrorCode::InvalidAmmPool);
+        require_keys_eq!(amm_info.pc_vault, accts.pool_pc_token_account.key(), ErrorCode::InvalidAmmPool);
+        let expected_out_amount = amm_info.swap_base_in_amount_out(
+            accts.pool_coin_token_account.amount,
+            accts.pool_pc_token_account.amount,
+            payment.pay_in_amount,
+            payment.pay_in_token == amm_info.coin_vault_mint,
+        )?;
+        require!(expected_out_amount >= payment.pay_out_amount, ErrorCode::InsufficientSwapOutput);
+
         //  Get initial to ata amount
         let initial_ata_balance: u64 = accts.to_ata.amount;
 
@@ -210,7 +497,7 @@ pub mod paydefi {
             &accts.payer.key(),
 
             payment.pay_in_amount,
-            0, //  min_out
+            minimum_amount_out,
         )?;
 
         invoke(
@@ -240,7 +527,9 @@ pub mod paydefi {
         accts.to_ata.reload()?;
         let to_ata_balance: u64 = accts.to_ata.amount;
         let swap_out_amount = to_ata_balance - initial_ata_balance;
-        let fee_amount = swap_out_amount - payment.pay_out_amount;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
 
         //  Transfer fee to treasury
         token::transfer(
@@ -268,6 +557,15 @@ pub mod paydefi {
             payment.pay_out_amount
         )?;
 
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
         // Emit an event after the successful payment
         emit!(SwapPaymentCompleted {
             order_id: payment.order_id,
@@ -283,158 +581,3580 @@ pub mod paydefi {
 
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct CompleteTransferPayment<'info> {
-    pub payer: Signer<'info>,
-    #[account(mut)]
-    pub from_ata: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub to_ata: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub treasury_ata: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>
-}
+    /// Swaps for exactly `pay_out_amount + fee_amount`, spending at most `pay_in_amount`.
+    ///
+    /// The quote signature covers the payment followed by `fee_amount`.
+    pub fn complete_swap_payment_exact_out(
+        ctx: Context<CompleteSwapPaymentExactOut>,
+        payment: Payment,
+        fee_amount: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
 
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct Payment {
-    pub order_id: String,
-    pub pay_in_token: Pubkey,
-    pub pay_out_token: Pubkey,
-    pub pay_in_amount: u64,
-    pub pay_out_amount: u64,
-    pub merchant: Pubkey,
-    pub expiry: i64, // Unix timestamp for expiration
-}
+        let accts = ctx.accounts;
 
-#[derive(Accounts)]
-pub struct CompleteTransferPaymentWithFee<'info> {
-    pub payer: Signer<'info>,
-    #[account(mut)]
-    pub from_ata: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub to_ata: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver1: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver2: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver3: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver4: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver5: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver6: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver7: Account<'info, TokenAccount>,
-    #[account(mut)]
-    pub receiver8: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-}
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
 
-#[derive(Accounts)]
-pub struct CompletePayment<'info> {
-    #[account(mut)]
-    payer: Signer<'info>,
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
 
-    /// CHECK: token receiver
-    #[account(mut)]
-    merchant: AccountInfo<'info>,
-    /// CHECK: treasury wallet address
-    #[account(mut)]
-    treasury: AccountInfo<'info>,
+        // Ensure the payment and fee were quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &(&payment, fee_amount).try_to_vec()?,
+        )?;
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    amm_id: AccountInfo<'info>,
-    /// CHECK: raydium will check
-    amm_authority: AccountInfo<'info>,
-    /// CHECK: raydium will check
-    #[account(mut)]
-    amm_open_orders: AccountInfo<'info>,
+        //  Get initial ata amounts
+        let initial_from_balance: u64 = accts.from_ata.amount;
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+        let amount_out = payment
+            .pay_out_amount
+            .checked_add(fee_amount)
+            .ok_or(ErrorCode::SwapAmountOverflow)?;
 
-    #[account(mut)]
-    pool_coin_token_account: Box<Account<'info, TokenAccount>>,
-    
-    #[account(mut)]
-    pool_pc_token_account: Box<Account<'info, TokenAccount>>,
-  
-    #[account(mut)]
-    from_ata: Box<Account<'info, TokenAccount>>,
-  
-    #[account(mut)]
-    to_ata: Box<Account<'info, TokenAccount>>,
-    
-    #[account(mut)]
-    treasury_ata: Box<Account<'info, TokenAccount>>,
+        //  Swap at most pay_in_amount for exactly amount_out via Raydium CPI
+        accts.raydium.swap_base_out(
+            &accts.token_program.to_account_info(),
+            &accts.from_ata.to_account_info(),
+            &accts.to_ata.to_account_info(),
+            &accts.payer.to_account_info(),
+            payment.pay_in_amount,
+            amount_out,
+        )?;
 
-    #[account(mut)]
-    merchant_ata: Box<Account<'info, TokenAccount>>,
-    
-    #[account(mut)]
-    /// CHECK: raydium will check
-    amm_target_orders: AccountInfo<'info>,
+        //  Get the amounts actually swapped
+        accts.from_ata.reload()?;
+        accts.to_ata.reload()?;
+        let swap_in_amount = initial_from_balance - accts.from_ata.amount;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        require!(swap_out_amount >= amount_out, ErrorCode::InsufficientSwapOutput);
 
-    /// CHECK: raydium will check
-    serum_program: AccountInfo<'info>,
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_market: AccountInfo<'info>,
+        //  Transfer fee to treasury
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount
+        )?;
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_bids: AccountInfo<'info>,
+        //  Transfer rest to merchant
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount
+        )?;
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_asks: AccountInfo<'info>,
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(&payment, swap_in_amount, accts.payer.key(), fee_amount, ctx.bumps.receipt)?;
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_event_queue: AccountInfo<'info>,
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: swap_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_coin_vault: AccountInfo<'info>,
+        Ok(())
+    }
 
-    #[account(mut)]
-    /// CHECK: raydium will check
-    serum_pc_vault: AccountInfo<'info>,
+    pub fn complete_swap_payment_with_fee<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompleteSwapPaymentWithFee<'info>>,
+        payment: Payment,
+        minimum_amount_out: u64,
+        fee_shares: Vec<FeeShare>,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
 
-    /// CHECK: raydium will check
-    serum_vault_signer: AccountInfo<'info>,
+        let accts = ctx.accounts;
 
-    /// CHECK: raydium will check
-    raydium_amm_program: AccountInfo<'info>,
-    token_program: Program<'info, Token>,
-    associated_token_program: Program<'info, AssociatedToken>,
-    system_program: Program<'info, System>,
-    rent: Sysvar<'info, Rent>,
-}
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
 
-#[event]
-pub struct PaymentCompleted {
-    pub order_id: String,
-    pub pay_in_token: Pubkey,
-    pub pay_out_token: Pubkey,
-    pub pay_in_amount: u64,
-    pub pay_out_amount: u64,
-    pub fee_collected: u64,
-    pub treasury: Pubkey,
-    pub merchant: Pubkey,
-    pub payer: Pubkey,
-}
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
 
-#[event]
-pub struct PaymentCompletedAndFeeDistributed {
-    pub order_id: String,
-    pub pay_in_token: Pubkey,
+        // Ensure the payment and its fee shares were quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &fee_shares_message(&payment, &fee_shares)?,
+        )?;
+
+        // Ensure there is exactly one receiver token account per fee share
+        require!(
+            fee_shares.len() <= MAX_FEE_SHARES && fee_shares.len() == ctx.remaining_accounts.len(),
+            ErrorCode::InvalidFeeShareCount
+        );
+
+        //  Get initial to ata amount
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+
+        //  Swap pay_in_token to pay_out_token via Raydium CPI
+        accts.raydium.swap_base_in(
+            &accts.token_program.to_account_info(),
+            &accts.from_ata.to_account_info(),
+            &accts.to_ata.to_account_info(),
+            &accts.payer.to_account_info(),
+            payment.pay_in_amount,
+            minimum_amount_out,
+        )?;
+
+        //  Get swap_out_amount
+        accts.to_ata.reload()?;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Split the swap surplus so that the shares add up to exactly the fee collected
+        let fee_amounts = split_fee_shares(fee_amount, &fee_shares)?;
+
+        //  Distribute the fee to each receiver
+        let mut fee_received = Vec::with_capacity(fee_shares.len());
+        let receivers = fee_shares.iter().zip(ctx.remaining_accounts.iter()).zip(fee_amounts);
+        for ((share, receiver_info), share_amount) in receivers {
+            let receiver_ata = Account::<TokenAccount>::try_from(receiver_info)?;
+            require!(
+                receiver_ata.owner == share.receiver && receiver_ata.mint == payment.pay_out_token,
+                ErrorCode::InvalidFeeReceiver
+            );
+
+            if share_amount > 0 {
+                token::transfer(
+                    CpiContext::new(
+                        accts.token_program.to_account_info(),
+                        token::Transfer {
+                            from: accts.to_ata.to_account_info(),
+                            to: receiver_info.clone(),
+                            authority: accts.payer.to_account_info()
+                        },
+                    ),
+                    share_amount
+                )?;
+            }
+
+            fee_received.push(FeeShareReceived {
+                receiver: receiver_info.key(),
+                amount: share_amount,
+            });
+        }
+
+        //  Transfer rest to merchant
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompletedAndFeeShared {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            fee_shares: fee_received,
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn complete_cpmm_swap_payment(
+        ctx: Context<CompleteCpmmSwapPayment>,
+        payment: Payment,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let accts = ctx.accounts;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Swap output is paid out as is, so the output mint must not charge transfer fees
+        let fee_config = transfer_fee_config(&accts.output_mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), payment.pay_out_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+
+        //  Get initial to ata amount
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+
+        //  Swap pay_in_token to pay_out_token via Raydium CPMM CPI
+        let swap_ix = cpmm_instruction::swap_base_input(
+            &accts.cpmm_program.key(),
+            &accts.payer.key(),
+            &accts.cpmm_authority.key(),
+            &accts.amm_config.key(),
+            &accts.pool_state.key(),
+            &accts.from_ata.key(),
+            &accts.to_ata.key(),
+            &accts.input_vault.key(),
+            &accts.output_vault.key(),
+            &accts.input_token_program.key(),
+            &accts.output_token_program.key(),
+            &accts.input_mint.key(),
+            &accts.output_mint.key(),
+            &accts.observation_state.key(),
+
+            payment.pay_in_amount,
+            minimum_amount_out,
+        )?;
+
+        invoke(
+            &swap_ix,
+            &[
+                accts.payer.to_account_info(),
+                accts.cpmm_authority.to_account_info(),
+                accts.amm_config.to_account_info(),
+                accts.pool_state.to_account_info(),
+                accts.from_ata.to_account_info(),
+                accts.to_ata.to_account_info(),
+                accts.input_vault.to_account_info(),
+                accts.output_vault.to_account_info(),
+                accts.input_token_program.to_account_info(),
+                accts.output_token_program.to_account_info(),
+                accts.input_mint.to_account_info(),
+                accts.output_mint.to_account_info(),
+                accts.observation_state.to_account_info(),
+            ],
+        )?;
+
+        //  Get swap_out_amount
+        accts.to_ata.reload()?;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Transfer fee to treasury
+        token_interface::transfer_checked(
+            CpiContext::new(
+                accts.output_token_program.to_account_info(),
+                TransferChecked {
+                    from: accts.to_ata.to_account_info(),
+                    mint: accts.output_mint.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount,
+            accts.output_mint.decimals,
+        )?;
+
+        //  Transfer rest to merchant
+        token_interface::transfer_checked(
+            CpiContext::new(
+                accts.output_token_program.to_account_info(),
+                TransferChecked {
+                    from: accts.to_ata.to_account_info(),
+                    mint: accts.output_mint.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount,
+            accts.output_mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn complete_whirlpool_swap_payment(
+        ctx: Context<CompleteWhirlpoolSwapPayment>,
+        payment: Payment,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let accts = ctx.accounts;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        //  Work out the swap direction from the pool vaults
+        let a_to_b = accts.token_vault_a.mint == payment.pay_in_token;
+        let (token_owner_account_a, token_owner_account_b, sqrt_price_limit) = if a_to_b {
+            (&accts.from_ata, &accts.to_ata, whirlpool_instruction::MIN_SQRT_PRICE_X64)
+        } else {
+            (&accts.to_ata, &accts.from_ata, whirlpool_instruction::MAX_SQRT_PRICE_X64)
+        };
+
+        //  Get initial to ata amount
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+
+        //  Swap pay_in_token to pay_out_token via Whirlpool CPI
+        let swap_ix = whirlpool_instruction::swap(
+            &accts.whirlpool_program.key(),
+            &accts.payer.key(),
+            &accts.whirlpool.key(),
+            &token_owner_account_a.key(),
+            &accts.token_vault_a.key(),
+            &token_owner_account_b.key(),
+            &accts.token_vault_b.key(),
+            &accts.tick_array_0.key(),
+            &accts.tick_array_1.key(),
+            &accts.tick_array_2.key(),
+            &accts.oracle.key(),
+
+            payment.pay_in_amount,
+            minimum_amount_out,
+            sqrt_price_limit,
+            true,
+            a_to_b,
+        )?;
+
+        invoke(
+            &swap_ix,
+            &[
+                accts.token_program.to_account_info(),
+                accts.payer.to_account_info(),
+                accts.whirlpool.to_account_info(),
+                token_owner_account_a.to_account_info(),
+                accts.token_vault_a.to_account_info(),
+                token_owner_account_b.to_account_info(),
+                accts.token_vault_b.to_account_info(),
+                accts.tick_array_0.to_account_info(),
+                accts.tick_array_1.to_account_info(),
+                accts.tick_array_2.to_account_info(),
+                accts.oracle.to_account_info(),
+            ],
+        )?;
+
+        //  Get swap_out_amount
+        accts.to_ata.reload()?;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Transfer fee to treasury
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount
+        )?;
+
+        //  Transfer rest to merchant
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Swaps through an allowlisted program with client supplied instruction data.
+    ///
+    /// The swap accounts are passed as remaining accounts, in the order the swap program expects.
+    pub fn complete_routed_swap_payment<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CompleteRoutedSwapPayment<'info>>,
+        payment: Payment,
+        swap_data: Vec<u8>,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let accts = ctx.accounts;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Swap output is paid out as is, so the output mint must not charge transfer fees
+        let fee_config = transfer_fee_config(&accts.output_mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), payment.pay_out_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+
+        //  Get initial ata amounts
+        let initial_from_balance: u64 = accts.from_ata.amount;
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+
+        //  Swap pay_in_token to pay_out_token via the routed program
+        let swap_ix = Instruction {
+            program_id: accts.swap_program.key(),
+            accounts: ctx
+                .remaining_accounts
+                .iter()
+                .map(|account| AccountMeta {
+                    pubkey: account.key(),
+                    is_signer: account.is_signer,
+                    is_writable: account.is_writable,
+                })
+                .collect(),
+            data: swap_data,
+        };
+        invoke(&swap_ix, ctx.remaining_accounts)?;
+
+        //  Check the route stayed within the quoted amounts
+        accts.from_ata.reload()?;
+        accts.to_ata.reload()?;
+        let swap_in_amount = initial_from_balance.saturating_sub(accts.from_ata.amount);
+        require!(swap_in_amount <= payment.pay_in_amount, ErrorCode::ExcessiveSwapInput);
+        let swap_out_amount = accts.to_ata.amount.saturating_sub(initial_ata_balance);
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Transfer fee to treasury
+        token_interface::transfer_checked(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                TransferChecked {
+                    from: accts.to_ata.to_account_info(),
+                    mint: accts.output_mint.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount,
+            accts.output_mint.decimals,
+        )?;
+
+        //  Transfer rest to merchant
+        token_interface::transfer_checked(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                TransferChecked {
+                    from: accts.to_ata.to_account_info(),
+                    mint: accts.output_mint.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount,
+            accts.output_mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(&payment, swap_in_amount, accts.payer.key(), fee_amount, ctx.bumps.receipt)?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: swap_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Swaps pay_in_token into pay_out_token through an intermediate token over two pools.
+    pub fn complete_multi_hop_swap_payment(
+        ctx: Context<CompleteMultiHopSwapPayment>,
+        payment: Payment,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let accts = ctx.accounts;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        //  Swap pay_in_token to the intermediate token via the first pool
+        let initial_intermediate_balance: u64 = accts.intermediate_ata.amount;
+        accts.first_pool.swap_base_in(
+            &accts.token_program.to_account_info(),
+            &accts.from_ata.to_account_info(),
+            &accts.intermediate_ata.to_account_info(),
+            &accts.payer.to_account_info(),
+            payment.pay_in_amount,
+            0,
+        )?;
+        accts.intermediate_ata.reload()?;
+        let intermediate_amount = accts.intermediate_ata.amount - initial_intermediate_balance;
+
+        //  Swap the intermediate token to pay_out_token via the second pool
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+        accts.second_pool.swap_base_in(
+            &accts.token_program.to_account_info(),
+            &accts.intermediate_ata.to_account_info(),
+            &accts.to_ata.to_account_info(),
+            &accts.payer.to_account_info(),
+            intermediate_amount,
+            minimum_amount_out,
+        )?;
+
+        //  Get swap_out_amount
+        accts.to_ata.reload()?;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Transfer fee to treasury
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount
+        )?;
+
+        //  Transfer rest to merchant
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn complete_sol_payment(ctx: Context<CompleteSolPayment>, payment: Payment) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let payer = &ctx.accounts.payer;
+        let merchant = &ctx.accounts.merchant;
+        let treasury = &ctx.accounts.treasury;
+        let system_program = &ctx.accounts.system_program;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Ensure the payment is quoted in SOL on both sides
+        require_keys_eq!(payment.pay_in_token, native_mint::ID, ErrorCode::InvalidPayInMint);
+        require_keys_eq!(payment.pay_out_token, native_mint::ID, ErrorCode::InvalidPayOutMint);
+
+        // Ensure the quote does not pay out more than it pays in
+        let fee_amount = payment
+            .pay_in_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InvalidPaymentAmounts)?;
+
+        // Transfer fee to the treasury if there is any fee
+        if fee_amount > 0 {
+            let cpi_accounts_fee = system_program::Transfer {
+                from: payer.to_account_info(),
+                to: treasury.to_account_info(),
+            };
+            let cpi_context_fee = CpiContext::new(system_program.to_account_info(), cpi_accounts_fee);
+            system_program::transfer(cpi_context_fee, fee_amount)?;
+        }
+
+        // Transfer lamports from payer to merchant
+        let cpi_accounts = system_program::Transfer {
+            from: payer.to_account_info(),
+            to: merchant.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+        system_program::transfer(cpi_context, payment.pay_out_amount)?;
+
+        // Record the settlement so the order cannot be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(PaymentCompleted {
+            order_id: payment.order_id.clone(),
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            transfer_fee_withheld: 0,
+            treasury: treasury.key(),
+            merchant: payment.merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn complete_sol_swap_payment(
+        ctx: Context<CompleteSolSwapPayment>,
+        payment: Payment,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let accts = ctx.accounts;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!accts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &accts.instructions,
+            &accts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        //  Wrap pay_in_amount into the temporary wSOL account
+        system_program::transfer(
+            CpiContext::new(
+                accts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: accts.payer.to_account_info(),
+                    to: accts.wsol_account.to_account_info(),
+                },
+            ),
+            payment.pay_in_amount,
+        )?;
+        token::sync_native(CpiContext::new(
+            accts.token_program.to_account_info(),
+            token::SyncNative {
+                account: accts.wsol_account.to_account_info(),
+            },
+        ))?;
+
+        //  Get initial to ata amount
+        let initial_ata_balance: u64 = accts.to_ata.amount;
+
+        //  Swap wSOL to pay_out_token via Raydium CPI
+        accts.raydium.swap_base_in(
+            &accts.token_program.to_account_info(),
+            &accts.wsol_account.to_account_info(),
+            &accts.to_ata.to_account_info(),
+            &accts.payer.to_account_info(),
+            payment.pay_in_amount,
+            minimum_amount_out,
+        )?;
+
+        //  Close the temporary wSOL account, returning its rent to the payer
+        token::close_account(CpiContext::new(
+            accts.token_program.to_account_info(),
+            token::CloseAccount {
+                account: accts.wsol_account.to_account_info(),
+                destination: accts.payer.to_account_info(),
+                authority: accts.payer.to_account_info(),
+            },
+        ))?;
+
+        //  Get swap_out_amount
+        accts.to_ata.reload()?;
+        let swap_out_amount = accts.to_ata.amount - initial_ata_balance;
+        let fee_amount = swap_out_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InsufficientSwapOutput)?;
+
+        //  Transfer fee to treasury
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.treasury_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            fee_amount
+        )?;
+
+        //  Transfer rest to merchant
+        token::transfer(
+            CpiContext::new(
+                accts.token_program.to_account_info(),
+                token::Transfer {
+                    from: accts.to_ata.to_account_info(),
+                    to: accts.merchant_ata.to_account_info(),
+                    authority: accts.payer.to_account_info()
+                },
+            ),
+            payment.pay_out_amount
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        accts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            accts.payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(SwapPaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: accts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: accts.payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Returns part or all of a settled order to its payer, signed by the merchant.
+    pub fn refund_payment(ctx: Context<RefundPayment>, order_id: String, amount: u64) -> Result<()> {
+        let mint = &ctx.accounts.mint;
+        let merchant = &ctx.accounts.merchant;
+        let receipt = &mut ctx.accounts.receipt;
+
+        // Ensure the refunds stay within what the merchant received
+        let refunded_amount = receipt
+            .refunded_amount
+            .checked_add(amount)
+            .filter(|refunded| *refunded <= receipt.pay_out_amount)
+            .ok_or(ErrorCode::RefundExceedsPayment)?;
+        receipt.refunded_amount = refunded_amount;
+
+        // Transfer tokens from the merchant back to the payer
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.merchant_ata.to_account_info(),
+            mint: mint.to_account_info(),
+            to: ctx.accounts.payer_ata.to_account_info(),
+            authority: merchant.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_context, amount, mint.decimals)?;
+
+        emit!(PaymentRefunded {
+            order_id,
+            token: receipt.pay_out_token,
+            amount,
+            refunded_amount,
+            merchant: merchant.key(),
+            payer: receipt.payer
+        });
+
+        Ok(())
+    }
+
+    /// Holds pay_in_amount in a vault until the merchant confirms fulfilment.
+    ///
+    /// The merchant may release the escrow until `release_deadline`, the payer may refund it after.
+    pub fn create_escrow_payment(
+        ctx: Context<CreateEscrowPayment>,
+        payment: Payment,
+        release_deadline: i64,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let payer = &ctx.accounts.payer;
+        let mint = &ctx.accounts.mint;
+
+        // Ensure the transaction has not expired
+        if Clock::get()?.unix_timestamp > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+        require!(release_deadline >= payment.expiry, ErrorCode::InvalidReleaseDeadline);
+
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the escrow was quoted by the configured signer, a direct payment quote does not match
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &(&payment, release_deadline).try_to_vec()?,
+        )?;
+
+        // Escrowed amounts are paid out as is, so the mint must not charge transfer fees
+        let fee_config = transfer_fee_config(mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), payment.pay_in_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+        require!(payment.pay_in_amount >= payment.pay_out_amount, ErrorCode::InvalidEscrowAmount);
+
+        // Move the funds from the payer into the vault
+        token_interface::transfer_checked(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.from_ata.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.vault.to_account_info(),
+                    authority: payer.to_account_info(),
+                },
+            ),
+            payment.pay_in_amount,
+            mint.decimals,
+        )?;
+
+        let escrow = &mut ctx.accounts.escrow;
+        escrow.payer = payer.key();
+        escrow.merchant = payment.merchant;
+        escrow.mint = payment.pay_in_token;
+        escrow.pay_in_amount = payment.pay_in_amount;
+        escrow.pay_out_amount = payment.pay_out_amount;
+        escrow.release_deadline = release_deadline;
+        escrow.bump = ctx.bumps.escrow;
+
+        emit!(EscrowCreated {
+            order_id: payment.order_id,
+            mint: payment.pay_in_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            release_deadline,
+            merchant: payment.merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Pays out an escrow to the merchant and treasury, signed by the merchant or the admin.
+    pub fn release_escrow(ctx: Context<ReleaseEscrow>, order_id: String) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let escrow = &ctx.accounts.escrow;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+
+        // Ensure the release deadline has not passed, the payer may refund it from then on
+        if Clock::get()?.unix_timestamp > escrow.release_deadline {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        let order_seed = order_seed(&order_id);
+        let signer_seeds: &[&[&[u8]]] = &[&[ESCROW_SEED, escrow.merchant.as_ref(), &order_seed, &[escrow.bump]]];
+        let fee_amount = escrow.pay_in_amount - escrow.pay_out_amount;
+
+        // Transfer fee to the treasury account if there is any fee
+        if fee_amount > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.vault.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: ctx.accounts.treasury_ata.to_account_info(),
+                        authority: escrow.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                fee_amount,
+                mint.decimals,
+            )?;
+        }
+
+        // Transfer tokens from the vault to the merchant
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.merchant_ata.to_account_info(),
+                    authority: escrow.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            escrow.pay_out_amount,
+            mint.decimals,
+        )?;
+
+        // Return the vault rent to the payer
+        close_escrow_vault(
+            token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.payer,
+            escrow,
+            signer_seeds,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        let payment = escrow.payment(order_id);
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            escrow.payer,
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        // Emit an event after the successful payment
+        emit!(PaymentCompleted {
+            order_id: payment.order_id,
+            pay_in_token: payment.pay_in_token,
+            pay_out_token: payment.pay_out_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            transfer_fee_withheld: 0,
+            treasury: ctx.accounts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: escrow.payer
+        });
+
+        Ok(())
+    }
+
+    /// Returns an expired escrow to the payer.
+    pub fn refund_escrow(ctx: Context<RefundEscrow>, order_id: String) -> Result<()> {
+        let escrow = &ctx.accounts.escrow;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+
+        // Ensure the merchant had until the release deadline to release the escrow
+        require!(
+            Clock::get()?.unix_timestamp > escrow.release_deadline,
+            ErrorCode::EscrowNotExpired
+        );
+
+        let order_seed = order_seed(&order_id);
+        let signer_seeds: &[&[&[u8]]] = &[&[ESCROW_SEED, escrow.merchant.as_ref(), &order_seed, &[escrow.bump]]];
+
+        // Transfer the whole amount back to the payer
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.payer_ata.to_account_info(),
+                    authority: escrow.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            escrow.pay_in_amount,
+            mint.decimals,
+        )?;
+
+        close_escrow_vault(
+            token_program,
+            &ctx.accounts.vault,
+            &ctx.accounts.payer,
+            escrow,
+            signer_seeds,
+        )?;
+
+        emit!(EscrowRefunded {
+            order_id,
+            mint: escrow.mint,
+            amount: escrow.pay_in_amount,
+            merchant: escrow.merchant,
+            payer: escrow.payer
+        });
+
+        Ok(())
+    }
+
+    /// Starts a subscription and approves its PDA to charge `periods` periods from the payer.
+    ///
+    /// A token account has a single delegate, so it backs one subscription at a time.
+    pub fn create_subscription(
+        ctx: Context<CreateSubscription>,
+        terms: SubscriptionTerms,
+        periods: u32,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let payer = &ctx.accounts.payer;
+        let mint = &ctx.accounts.mint;
+
+        // Ensure the terms have not expired
+        let now = Clock::get()?.unix_timestamp;
+        if now > terms.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the terms were quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &terms.try_to_vec()?,
+        )?;
+
+        require!(terms.period > 0, ErrorCode::InvalidSubscriptionPeriod);
+        require!(terms.trial_period >= 0, ErrorCode::InvalidSubscriptionPeriod);
+        let next_due = now
+            .checked_add(terms.trial_period)
+            .ok_or(ErrorCode::InvalidSubscriptionPeriod)?;
+
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.payer = payer.key();
+        subscription.merchant = terms.merchant;
+        subscription.mint = terms.mint;
+        subscription.amount = terms.amount;
+        subscription.fee = terms.fee;
+        subscription.period = terms.period;
+        subscription.next_due = next_due;
+        subscription.paused_at = 0;
+        subscription.paused_by = Pubkey::default();
+        subscription.pending_amount = None;
+        subscription.bump = ctx.bumps.subscription;
+
+        // Charges are paid out as is, so the mint must not charge transfer fees
+        let charge_amount = subscription.charge_amount()?;
+        let fee_config = transfer_fee_config(mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), charge_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+
+        // Let the subscription pull the approved number of periods
+        let allowance = approve_subscription(
+            &ctx.accounts.token_program,
+            &ctx.accounts.payer_ata,
+            payer,
+            subscription,
+            periods,
+        )?;
+
+        emit!(SubscriptionCreated {
+            plan_id: terms.plan_id,
+            mint: terms.mint,
+            amount: terms.amount,
+            fee: terms.fee,
+            period: terms.period,
+            trial_period: terms.trial_period,
+            next_due,
+            allowance,
+            merchant: terms.merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Charges one period of a due subscription, callable by anyone.
+    ///
+    /// Periods missed entirely are not charged: a late charge pays for the current period
+    /// and moves `next_due` to the start of the following one, so arrears cannot be pulled.
+    pub fn charge_subscription(ctx: Context<ChargeSubscription>, plan_id: String) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let subscription = &ctx.accounts.subscription;
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+
+        // Ensure neither party paused the subscription
+        require!(!subscription.is_paused(), ErrorCode::SubscriptionPaused);
+
+        // Ensure the current period is due
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= subscription.next_due, ErrorCode::SubscriptionNotDue);
+
+        let plan_seed = order_seed(&plan_id);
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            SUBSCRIPTION_SEED,
+            subscription.payer.as_ref(),
+            subscription.merchant.as_ref(),
+            &plan_seed,
+            &[subscription.bump],
+        ]];
+
+        // Transfer fee to the treasury account if there is any fee
+        if subscription.fee > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.payer_ata.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: ctx.accounts.treasury_ata.to_account_info(),
+                        authority: subscription.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                subscription.fee,
+                mint.decimals,
+            )?;
+        }
+
+        // Transfer tokens from payer to merchant
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_ata.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.merchant_ata.to_account_info(),
+                    authority: subscription.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            subscription.amount,
+            mint.decimals,
+        )?;
+
+        // Move on to the period after the current one, skipping missed periods
+        let subscription = &mut ctx.accounts.subscription;
+        let missed = (now - subscription.next_due) / subscription.period;
+        subscription.next_due += (missed + 1) * subscription.period;
+
+        emit!(SubscriptionCharged {
+            plan_id,
+            mint: subscription.mint,
+            amount: subscription.amount,
+            fee_collected: subscription.fee,
+            next_due: subscription.next_due,
+            treasury: ctx.accounts.treasury_ata.key(),
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Pauses charging, signed by the payer or the merchant.
+    pub fn pause_subscription(ctx: Context<ManageSubscription>, plan_id: String) -> Result<()> {
+        let subscription = &mut ctx.accounts.subscription;
+        require!(!subscription.is_paused(), ErrorCode::SubscriptionPaused);
+
+        subscription.paused_at = Clock::get()?.unix_timestamp;
+        subscription.paused_by = ctx.accounts.authority.key();
+
+        emit!(SubscriptionPaused {
+            plan_id,
+            paused_at: subscription.paused_at,
+            authority: ctx.accounts.authority.key(),
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Resumes charging, signed by the party that paused or by the payer.
+    ///
+    /// The next charge is pushed back by the time spent paused.
+    pub fn resume_subscription(ctx: Context<ManageSubscription>, plan_id: String) -> Result<()> {
+        let subscription = &mut ctx.accounts.subscription;
+        require!(subscription.is_paused(), ErrorCode::SubscriptionNotPaused);
+
+        // Ensure a merchant cannot resume charges the payer paused
+        let authority = ctx.accounts.authority.key();
+        require!(
+            authority == subscription.paused_by || authority == subscription.payer,
+            ErrorCode::Unauthorized
+        );
+
+        let paused_for = Clock::get()?.unix_timestamp - subscription.paused_at;
+        subscription.next_due += paused_for;
+        subscription.paused_at = 0;
+        subscription.paused_by = Pubkey::default();
+
+        emit!(SubscriptionResumed {
+            plan_id,
+            next_due: subscription.next_due,
+            authority: ctx.accounts.authority.key(),
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Proposes a new amount per period, charged only once the payer accepts it.
+    pub fn propose_subscription_price(
+        ctx: Context<ProposeSubscriptionPrice>,
+        plan_id: String,
+        amount: u64,
+    ) -> Result<()> {
+        let subscription = &mut ctx.accounts.subscription;
+        subscription.pending_amount = Some(amount);
+
+        emit!(SubscriptionPriceProposed {
+            plan_id,
+            amount: subscription.amount,
+            pending_amount: amount,
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Accepts the proposed amount and approves `periods` periods at the new price.
+    pub fn accept_subscription_price(
+        ctx: Context<AcceptSubscriptionPrice>,
+        plan_id: String,
+        amount: u64,
+        periods: u32,
+    ) -> Result<()> {
+        let subscription = &mut ctx.accounts.subscription;
+
+        // Ensure the payer consents to the amount currently proposed
+        require!(
+            subscription.pending_amount == Some(amount),
+            ErrorCode::SubscriptionPriceMismatch
+        );
+        subscription.amount = amount;
+        subscription.pending_amount = None;
+
+        let allowance = approve_subscription(
+            &ctx.accounts.token_program,
+            &ctx.accounts.payer_ata,
+            &ctx.accounts.payer,
+            subscription,
+            periods,
+        )?;
+
+        emit!(SubscriptionPriceAccepted {
+            plan_id,
+            amount,
+            allowance,
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Ends a subscription, revoking its delegation and returning the rent to the payer.
+    pub fn cancel_subscription(ctx: Context<CancelSubscription>, plan_id: String) -> Result<()> {
+        let subscription = &ctx.accounts.subscription;
+        let payer_ata = &ctx.accounts.payer_ata;
+
+        // Leave delegations to anything else in place
+        if payer_ata.delegate == COption::Some(subscription.key()) {
+            token_interface::revoke(CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Revoke {
+                    source: payer_ata.to_account_info(),
+                    authority: ctx.accounts.payer.to_account_info(),
+                },
+            ))?;
+        }
+
+        emit!(SubscriptionCancelled {
+            plan_id,
+            merchant: subscription.merchant,
+            payer: subscription.payer
+        });
+
+        Ok(())
+    }
+
+    /// Lets a merchant pull up to `cap` per period from the payer, approved for `periods` periods.
+    ///
+    /// All authorizations of a payer and mint share one delegate PDA, each keeping its own
+    /// share of the delegated amount, so several merchants can be authorized at once.
+    pub fn create_spending_authorization(
+        ctx: Context<CreateSpendingAuthorization>,
+        merchant: Pubkey,
+        cap: u64,
+        period: i64,
+        periods: u32,
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        require!(period > 0, ErrorCode::InvalidSpendingPeriod);
+
+        let payer = &ctx.accounts.payer;
+        let mint = &ctx.accounts.mint;
+        let now = Clock::get()?.unix_timestamp;
+
+        let authorization = &mut ctx.accounts.authorization;
+        authorization.payer = payer.key();
+        authorization.merchant = merchant;
+        authorization.mint = mint.key();
+        authorization.cap = cap;
+        authorization.period = period;
+        authorization.period_start = now;
+        authorization.spent = 0;
+
+        // Let the authorization pull the cap of every approved period
+        let allowance = cap
+            .checked_mul(periods as u64)
+            .ok_or(ErrorCode::SpendingAllowanceOverflow)?;
+        authorization.allowance = allowance;
+        authorization.bump = ctx.bumps.authorization;
+        authorization.delegate_bump = ctx.bumps.delegate;
+
+        // Add the allowance to what the other authorizations were approved for
+        let delegated_amount = delegated_amount(&ctx.accounts.payer_ata, &ctx.accounts.delegate.key())?
+            .checked_add(allowance)
+            .ok_or(ErrorCode::SpendingAllowanceOverflow)?;
+        token_interface::approve(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token_interface::Approve {
+                    to: ctx.accounts.payer_ata.to_account_info(),
+                    delegate: ctx.accounts.delegate.to_account_info(),
+                    authority: payer.to_account_info(),
+                },
+            ),
+            delegated_amount,
+        )?;
+
+        emit!(SpendingAuthorized {
+            mint: mint.key(),
+            cap,
+            period,
+            period_start: now,
+            allowance,
+            merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    /// Pulls a quoted payment from the payer within the authorized cap, signed by the merchant.
+    pub fn pull_payment(ctx: Context<PullPayment>, payment: Payment) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let mint = &ctx.accounts.mint;
+        let token_program = &ctx.accounts.token_program;
+
+        // Ensure the transaction has not expired
+        let now = Clock::get()?.unix_timestamp;
+        if now > payment.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the order has not been settled already
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the payment was quoted by the configured signer
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Pulled amounts are paid out as is, so the mint must not charge transfer fees
+        let fee_config = transfer_fee_config(mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), payment.pay_in_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+        let fee_amount = payment
+            .pay_in_amount
+            .checked_sub(payment.pay_out_amount)
+            .ok_or(ErrorCode::InvalidPullAmount)?;
+
+        // Ensure the pull fits in what is left of the current period's cap
+        let authorization = &mut ctx.accounts.authorization;
+        authorization.roll_period(now);
+        let spent = authorization
+            .spent
+            .checked_add(payment.pay_in_amount)
+            .filter(|spent| *spent <= authorization.cap)
+            .ok_or(ErrorCode::SpendingCapExceeded)?;
+        authorization.spent = spent;
+
+        // Ensure the pull stays within this authorization's share of the delegated amount
+        authorization.allowance = authorization
+            .allowance
+            .checked_sub(payment.pay_in_amount)
+            .ok_or(ErrorCode::SpendingAllowanceExhausted)?;
+
+        let authorization = &ctx.accounts.authorization;
+        let delegate = &ctx.accounts.delegate;
+        let signer_seeds: &[&[&[u8]]] = &[&[
+            SPENDING_DELEGATE_SEED,
+            authorization.payer.as_ref(),
+            authorization.mint.as_ref(),
+            &[authorization.delegate_bump],
+        ]];
+
+        // Transfer fee to the treasury account if there is any fee
+        if fee_amount > 0 {
+            token_interface::transfer_checked(
+                CpiContext::new_with_signer(
+                    token_program.to_account_info(),
+                    TransferChecked {
+                        from: ctx.accounts.payer_ata.to_account_info(),
+                        mint: mint.to_account_info(),
+                        to: ctx.accounts.treasury_ata.to_account_info(),
+                        authority: delegate.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                fee_amount,
+                mint.decimals,
+            )?;
+        }
+
+        // Transfer tokens from payer to merchant
+        token_interface::transfer_checked(
+            CpiContext::new_with_signer(
+                token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.payer_ata.to_account_info(),
+                    mint: mint.to_account_info(),
+                    to: ctx.accounts.merchant_ata.to_account_info(),
+                    authority: delegate.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            payment.pay_out_amount,
+            mint.decimals,
+        )?;
+
+        // Record the settlement so the order cannot be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            authorization.payer,
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+
+        emit!(PaymentPulled {
+            order_id: payment.order_id,
+            mint: payment.pay_in_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            spent,
+            period_start: authorization.period_start,
+            treasury: ctx.accounts.treasury_ata.key(),
+            merchant: payment.merchant,
+            payer: authorization.payer
+        });
+
+        Ok(())
+    }
+
+    /// Withdraws a spending authorization, removing its share of the delegated amount.
+    pub fn revoke_spending_authorization(ctx: Context<RevokeSpendingAuthorization>) -> Result<()> {
+        let authorization = &ctx.accounts.authorization;
+        let payer_ata = &ctx.accounts.payer_ata;
+        let token_program = &ctx.accounts.token_program;
+        let payer = &ctx.accounts.payer;
+
+        // Leave delegations to anything else in place
+        if payer_ata.delegate == COption::Some(ctx.accounts.delegate.key()) {
+            let remaining = payer_ata.delegated_amount.saturating_sub(authorization.allowance);
+            if remaining == 0 {
+                token_interface::revoke(CpiContext::new(
+                    token_program.to_account_info(),
+                    token_interface::Revoke {
+                        source: payer_ata.to_account_info(),
+                        authority: payer.to_account_info(),
+                    },
+                ))?;
+            } else {
+                // Keep the shares of the payer's other authorizations
+                token_interface::approve(
+                    CpiContext::new(
+                        token_program.to_account_info(),
+                        token_interface::Approve {
+                            to: payer_ata.to_account_info(),
+                            delegate: ctx.accounts.delegate.to_account_info(),
+                            authority: payer.to_account_info(),
+                        },
+                    ),
+                    remaining,
+                )?;
+            }
+        }
+
+        emit!(SpendingAuthorizationRevoked {
+            mint: authorization.mint,
+            merchant: authorization.merchant,
+            payer: authorization.payer
+        });
+
+        Ok(())
+    }
+
+    /// Issues an invoice payable once in any of the mints of `amounts`, signed by the merchant.
+    ///
+    /// Each accepted mint carries its own amount, so mints of different decimals can be offered.
+    pub fn create_invoice(
+        ctx: Context<CreateInvoice>,
+        invoice_id: String,
+        amounts: Vec<InvoiceAmount>,
+        expiry: i64,
+        memo_hash: [u8; 32],
+    ) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        // Ensure the invoice can still be paid
+        if Clock::get()?.unix_timestamp > expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+        require!(
+            !amounts.is_empty() && amounts.len() <= MAX_INVOICE_MINTS,
+            ErrorCode::InvalidInvoiceMints
+        );
+
+        // Ensure every mint has a single amount
+        for (i, invoice_amount) in amounts.iter().enumerate() {
+            require!(
+                amounts[..i].iter().all(|other| other.mint != invoice_amount.mint),
+                ErrorCode::InvalidInvoiceMints
+            );
+        }
+
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.merchant = ctx.accounts.merchant.key();
+        invoice.amounts = amounts.clone();
+        invoice.expiry = expiry;
+        invoice.memo_hash = memo_hash;
+        invoice.payer = Pubkey::default();
+        invoice.paid_slot = 0;
+        invoice.bump = ctx.bumps.invoice;
+
+        emit!(InvoiceCreated {
+            invoice_id,
+            amounts,
+            expiry,
+            memo_hash,
+            merchant: invoice.merchant
+        });
+
+        Ok(())
+    }
+
+    /// Pays an invoice in `mint`, the merchant amount in that mint and the expiry are taken from the invoice.
+    ///
+    /// The quote signer signs the resulting payment to fix the fee on top of the amount.
+    pub fn pay_invoice(ctx: Context<PayInvoice>, invoice_id: String, fee_amount: u64) -> Result<()> {
+        // Ensure the program is not paused
+        require!(!ctx.accounts.config.paused, ErrorCode::ProgramPaused);
+
+        let invoice = &ctx.accounts.invoice;
+        let mint = &ctx.accounts.mint;
+        let payer = &ctx.accounts.payer;
+        let token_program = &ctx.accounts.token_program;
+
+        // Ensure the invoice has not expired
+        if Clock::get()?.unix_timestamp > invoice.expiry {
+            return Err(ErrorCode::PaymentExpired.into());
+        }
+
+        // Ensure the invoice and its order have not been paid already
+        require!(!invoice.is_paid(), ErrorCode::OrderAlreadyCompleted);
+        require!(!ctx.accounts.receipt.is_settled(), ErrorCode::OrderAlreadyCompleted);
+
+        // Ensure the fee was quoted by the configured signer
+        let payment = invoice.payment(invoice_id, mint.key(), fee_amount)?;
+        ed25519::verify_signature(
+            &ctx.accounts.instructions,
+            &ctx.accounts.config.quote_signer,
+            &payment.try_to_vec()?,
+        )?;
+
+        // Invoiced amounts are paid out as is, so the mint must not charge transfer fees
+        let fee_config = transfer_fee_config(mint)?;
+        require!(
+            transfer_fee(fee_config.as_ref(), payment.pay_in_amount)? == 0,
+            ErrorCode::TransferFeeNotAllowed
+        );
+
+        // Transfer fee to the treasury account if there is any fee
+        if fee_amount > 0 {
+            let cpi_accounts_fee = TransferChecked {
+                from: ctx.accounts.from_ata.to_account_info(),
+                mint: mint.to_account_info(),
+                to: ctx.accounts.treasury_ata.to_account_info(),
+                authority: payer.to_account_info(),
+            };
+            let cpi_context_fee = CpiContext::new(token_program.to_account_info(), cpi_accounts_fee);
+            token_interface::transfer_checked(cpi_context_fee, fee_amount, mint.decimals)?;
+        }
+
+        // Transfer tokens from payer to merchant
+        let cpi_accounts = TransferChecked {
+            from: ctx.accounts.from_ata.to_account_info(),
+            mint: mint.to_account_info(),
+            to: ctx.accounts.merchant_ata.to_account_info(),
+            authority: payer.to_account_info(),
+        };
+        let cpi_context = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+        token_interface::transfer_checked(cpi_context, payment.pay_out_amount, mint.decimals)?;
+
+        // Record the settlement so neither the invoice nor the order can be paid twice
+        ctx.accounts.receipt.settle(
+            &payment,
+            payment.pay_in_amount,
+            payer.key(),
+            fee_amount,
+            ctx.bumps.receipt,
+        )?;
+        let invoice = &mut ctx.accounts.invoice;
+        invoice.payer = payer.key();
+        invoice.paid_slot = Clock::get()?.slot;
+
+        emit!(InvoicePaid {
+            invoice_id: payment.order_id,
+            memo_hash: invoice.memo_hash,
+            mint: payment.pay_in_token,
+            pay_in_amount: payment.pay_in_amount,
+            pay_out_amount: payment.pay_out_amount,
+            fee_collected: fee_amount,
+            treasury: ctx.accounts.treasury_ata.key(),
+            merchant: invoice.merchant,
+            payer: payer.key()
+        });
+
+        Ok(())
+    }
+
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        treasury_owner: Pubkey,
+        quote_signer: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.treasury_owner = treasury_owner;
+        config.quote_signer = quote_signer;
+        config.paused = false;
+        config.bump = ctx.bumps.config;
+
+        Ok(())
+    }
+
+    pub fn update_config(
+        ctx: Context<UpdateConfig>,
+        admin: Pubkey,
+        treasury_owner: Pubkey,
+        quote_signer: Pubkey,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.admin = admin;
+        config.treasury_owner = treasury_owner;
+        config.quote_signer = quote_signer;
+
+        Ok(())
+    }
+
+    pub fn pause(ctx: Context<UpdateConfig>) -> Result<()> {
+        set_paused(&mut ctx.accounts.config, true)
+    }
+
+    pub fn unpause(ctx: Context<UpdateConfig>) -> Result<()> {
+        set_paused(&mut ctx.accounts.config, false)
+    }
+
+    pub fn add_swap_program(ctx: Context<AddSwapProgram>, program_id: Pubkey) -> Result<()> {
+        let allowed_swap_program = &mut ctx.accounts.allowed_swap_program;
+        allowed_swap_program.program_id = program_id;
+        allowed_swap_program.bump = ctx.bumps.allowed_swap_program;
+
+        Ok(())
+    }
+
+    pub fn remove_swap_program(_ctx: Context<RemoveSwapProgram>) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn set_paused(config: &mut Account<Config>, paused: bool) -> Result<()> {
+    config.paused = paused;
+
+    emit!(PauseToggled {
+        paused,
+        admin: config.admin,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteTransferPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_in_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Payment {
+    pub order_id: String,
+    pub pay_in_token: Pubkey,
+    pub pay_out_token: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub merchant: Pubkey,
+    pub expiry: i64, // Unix timestamp for expiration
+    pub gross_up_transfer_fee: bool, // Pay Token-2022 transfer fees on top instead of rejecting
+}
+
+/// Fee receiver token accounts are bound to the quote, which signs their keys with the percentages.
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteTransferPaymentWithFee<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = receiver1.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver1: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver2.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver2: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver3.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver3: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver4.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver4: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver5.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver5: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver6.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver6: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver7.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver7: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, constraint = receiver8.mint == payment.pay_in_token @ ErrorCode::InvalidFeeReceiver)]
+    pub receiver8: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Fee receiver token accounts are passed as remaining accounts, in the order of the shares.
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteTransferPaymentWithFeeShares<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeShare {
+    pub receiver: Pubkey, // Owner of the receiving token account
+    pub bps: u16,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SubscriptionTerms {
+    pub plan_id: String,
+    pub merchant: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64, // Paid to the merchant every period
+    pub fee: u64, // Paid to the treasury every period
+    pub period: i64, // Seconds between charges
+    pub trial_period: i64, // Seconds before the first charge
+    pub expiry: i64, // Unix timestamp for expiration of the terms
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct InvoiceAmount {
+    pub mint: Pubkey,
+    pub amount: u64, // Paid to the merchant when the invoice is paid in this mint
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompletePayment<'info> {
+    #[account(mut)]
+    payer: Signer<'info>,
+
+    /// CHECK: token receiver
+    #[account(mut, address = payment.merchant @ ErrorCode::InvalidMerchantAccount)]
+    merchant: AccountInfo<'info>,
+    /// CHECK: treasury wallet address
+    #[account(mut, address = config.treasury_owner @ ErrorCode::InvalidTreasuryAccount)]
+    treasury: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    amm_id: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    amm_authority: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    amm_open_orders: AccountInfo<'info>,
+
+    #[account(mut)]
+    pool_coin_token_account: Box<Account<'info, TokenAccount>>,
+    
+    #[account(mut)]
+    pool_pc_token_account: Box<Account<'info, TokenAccount>>,
+  
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    from_ata: Box<Account<'info, TokenAccount>>,
+  
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    to_ata: Box<Account<'info, TokenAccount>>,
+    
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    treasury_ata: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    merchant_ata: Box<Account<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    receipt: Box<Account<'info, OrderReceipt>>,
+
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    config: Box<Account<'info, Config>>,
+
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    instructions: UncheckedAccount<'info>,
+    
+    #[account(mut)]
+    /// CHECK: raydium will check
+    amm_target_orders: AccountInfo<'info>,
+
+    /// CHECK: raydium will check
+    serum_program: AccountInfo<'info>,
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_market: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_bids: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_asks: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_event_queue: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_coin_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    /// CHECK: raydium will check
+    serum_pc_vault: AccountInfo<'info>,
+
+    /// CHECK: raydium will check
+    serum_vault_signer: AccountInfo<'info>,
+
+    /// CHECK: raydium will check
+    raydium_amm_program: AccountInfo<'info>,
+    token_program: Program<'info, Token>,
+    associated_token_program: Program<'info, AssociatedToken>,
+    system_program: Program<'info, System>,
+    rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteSwapPaymentExactOut<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<Account<'info, TokenAccount>>,
+    pub raydium: RaydiumSwap<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Fee receiver token accounts are passed as remaining accounts, in the order of the shares.
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteSwapPaymentWithFee<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<Account<'info, TokenAccount>>,
+    pub raydium: RaydiumSwap<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteCpmmSwapPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(address = payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub input_mint: Box<InterfaceAccount<'info, Mint>>,
+    #[account(address = payment.pay_out_token @ ErrorCode::InvalidPayOutMint)]
+    pub output_mint: Box<InterfaceAccount<'info, Mint>>,
+    /// CHECK: raydium cpmm program
+    #[account(address = cpmm_instruction::ID)]
+    pub cpmm_program: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    pub cpmm_authority: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    pub amm_config: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub pool_state: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub input_vault: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub output_vault: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub observation_state: AccountInfo<'info>,
+    pub input_token_program: Interface<'info, TokenInterface>,
+    pub output_token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteWhirlpoolSwapPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<Account<'info, TokenAccount>>,
+    /// CHECK: whirlpool program
+    #[account(address = whirlpool_instruction::ID)]
+    pub whirlpool_program: AccountInfo<'info>,
+    /// CHECK: whirlpool will check
+    #[account(mut)]
+    pub whirlpool: AccountInfo<'info>,
+    #[account(mut)]
+    pub token_vault_a: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub token_vault_b: Box<Account<'info, TokenAccount>>,
+    /// CHECK: whirlpool will check
+    #[account(mut)]
+    pub tick_array_0: AccountInfo<'info>,
+    /// CHECK: whirlpool will check
+    #[account(mut)]
+    pub tick_array_1: AccountInfo<'info>,
+    /// CHECK: whirlpool will check
+    #[account(mut)]
+    pub tick_array_2: AccountInfo<'info>,
+    /// CHECK: whirlpool will check
+    #[account(mut)]
+    pub oracle: AccountInfo<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteRoutedSwapPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    #[account(address = payment.pay_out_token @ ErrorCode::InvalidPayOutMint)]
+    pub output_mint: Box<InterfaceAccount<'info, Mint>>,
+    /// CHECK: checked against the allowlist
+    #[account(executable)]
+    pub swap_program: AccountInfo<'info>,
+    #[account(
+        seeds = [SWAP_PROGRAM_SEED, swap_program.key().as_ref()],
+        bump = allowed_swap_program.bump,
+        constraint = allowed_swap_program.program_id == swap_program.key() @ ErrorCode::SwapProgramNotAllowed
+    )]
+    pub allowed_swap_program: Box<Account<'info, AllowedSwapProgram>>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteMultiHopSwapPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: Box<Account<'info, TokenAccount>>,
+    #[account(mut, constraint = intermediate_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination)]
+    pub intermediate_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<Account<'info, TokenAccount>>,
+    pub first_pool: RaydiumSwap<'info>,
+    pub second_pool: RaydiumSwap<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteSolPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, address = payment.merchant @ ErrorCode::InvalidMerchantAccount)]
+    pub merchant: SystemAccount<'info>,
+    #[account(mut, address = config.treasury_owner @ ErrorCode::InvalidTreasuryAccount)]
+    pub treasury: SystemAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CompleteSolSwapPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        address = native_mint::ID @ ErrorCode::InvalidPayInMint,
+        constraint = payment.pay_in_token == native_mint.key() @ ErrorCode::InvalidPayInMint
+    )]
+    pub native_mint: Box<Account<'info, anchor_spl::token::Mint>>,
+    /// Temporary wSOL account, closed once the swap is done
+    #[account(
+        init,
+        payer = payer,
+        seeds = [WSOL_SEED, payer.key().as_ref()],
+        bump,
+        token::mint = native_mint,
+        token::authority = payer
+    )]
+    pub wsol_account: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = to_ata.owner == payer.key() @ ErrorCode::InvalidSwapDestination,
+        constraint = to_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub to_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_out_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: Box<Account<'info, TokenAccount>>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: Box<Account<'info, TokenAccount>>,
+    pub raydium: RaydiumSwap<'info>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Box<Account<'info, OrderReceipt>>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+/// Raydium AMM v4 pool and OpenBook market accounts needed for a swap.
+#[derive(Accounts)]
+pub struct RaydiumSwap<'info> {
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub amm_id: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    pub amm_authority: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub amm_open_orders: AccountInfo<'info>,
+    #[account(mut)]
+    pub pool_coin_token_account: Box<Account<'info, TokenAccount>>,
+    #[account(mut)]
+    pub pool_pc_token_account: Box<Account<'info, TokenAccount>>,
+    /// CHECK: raydium will check
+    pub serum_program: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_market: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_bids: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_asks: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_event_queue: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_coin_vault: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    #[account(mut)]
+    pub serum_pc_vault: AccountInfo<'info>,
+    /// CHECK: raydium will check
+    pub serum_vault_signer: AccountInfo<'info>,
+    /// CHECK: raydium amm program
+    #[account(address = amm_instruction::ID)]
+    pub raydium_amm_program: AccountInfo<'info>,
+}
+
+impl<'info> RaydiumSwap<'info> {
+    /// Swaps exactly `amount_in` from `source` into `destination`, both owned by `owner`.
+    pub fn swap_base_in(
+        &self,
+        token_program: &AccountInfo<'info>,
+        source: &AccountInfo<'info>,
+        destination: &AccountInfo<'info>,
+        owner: &AccountInfo<'info>,
+        amount_in: u64,
+        minimum_amount_out: u64,
+    ) -> Result<()> {
+        let swap_ix = amm_instruction::swap_base_in(
+            &self.raydium_amm_program.key(),
+            &self.amm_id.key(),
+            &self.amm_authority.key(),
+            &self.amm_open_orders.key(),
+            &self.pool_coin_token_account.key(),
+            &self.pool_pc_token_account.key(),
+            &self.serum_program.key(),
+            &self.serum_market.key(),
+            &self.serum_bids.key(),
+            &self.serum_asks.key(),
+            &self.serum_event_queue.key(),
+            &self.serum_coin_vault.key(),
+            &self.serum_pc_vault.key(),
+            &self.serum_vault_signer.key(),
+            source.key,
+            destination.key,
+            owner.key,
+            amount_in,
+            minimum_amount_out,
+        )?;
+        self.invoke(&swap_ix, token_program, source, destination, owner)
+    }
+
+    /// Swaps at most `max_amount_in` from `source` for exactly `amount_out` into `destination`.
+    pub fn swap_base_out(
+        &self,
+        token_program: &AccountInfo<'info>,
+        source: &AccountInfo<'info>,
+        destination: &AccountInfo<'info>,
+        owner: &AccountInfo<'info>,
+        max_amount_in: u64,
+        amount_out: u64,
+    ) -> Result<()> {
+        let swap_ix = amm_instruction::swap_base_out(
+            &self.raydium_amm_program.key(),
+            &self.amm_id.key(),
+            &self.amm_authority.key(),
+            &self.amm_open_orders.key(),
+            &self.pool_coin_token_account.key(),
+            &self.pool_pc_token_account.key(),
+            &self.serum_program.key(),
+            &self.serum_market.key(),
+            &self.serum_bids.key(),
+            &self.serum_asks.key(),
+            &self.serum_event_queue.key(),
+            &self.serum_coin_vault.key(),
+            &self.serum_pc_vault.key(),
+            &self.serum_vault_signer.key(),
+            source.key,
+            destination.key,
+            owner.key,
+            max_amount_in,
+            amount_out,
+        )?;
+        self.invoke(&swap_ix, token_program, source, destination, owner)
+    }
+
+    fn invoke(
+        &self,
+        swap_ix: &solana_program::instruction::Instruction,
+        token_program: &AccountInfo<'info>,
+        source: &AccountInfo<'info>,
+        destination: &AccountInfo<'info>,
+        owner: &AccountInfo<'info>,
+    ) -> Result<()> {
+        solana_program::program::invoke(
+            swap_ix,
+            &[
+                token_program.clone(),
+                self.amm_id.to_account_info(),
+                self.amm_authority.to_account_info(),
+                self.amm_open_orders.to_account_info(),
+                self.pool_coin_token_account.to_account_info(),
+                self.pool_pc_token_account.to_account_info(),
+                self.serum_program.to_account_info(),
+                self.serum_market.to_account_info(),
+                self.serum_bids.to_account_info(),
+                self.serum_asks.to_account_info(),
+                self.serum_event_queue.to_account_info(),
+                self.serum_coin_vault.to_account_info(),
+                self.serum_pc_vault.to_account_info(),
+                self.serum_vault_signer.to_account_info(),
+                source.clone(),
+                destination.clone(),
+                owner.clone(),
+            ],
+        )?;
+
+        Ok(())
+    }
+}
+
+fn close_escrow_vault<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    vault: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    destination: &AccountInfo<'info>,
+    escrow: &Account<'info, Escrow>,
+    signer_seeds: &[&[&[u8]]],
+) -> Result<()> {
+    token_interface::close_account(CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        token_interface::CloseAccount {
+            account: vault.to_account_info(),
+            destination: destination.to_account_info(),
+            authority: escrow.to_account_info(),
+        },
+        signer_seeds,
+    ))
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: String)]
+pub struct RefundPayment<'info> {
+    pub merchant: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [RECEIPT_SEED, merchant.key().as_ref(), &order_seed(&order_id)],
+        bump = receipt.bump,
+        constraint = receipt.is_settled() @ ErrorCode::OrderNotCompleted
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(address = receipt.pay_out_token @ ErrorCode::InvalidPayOutMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == merchant.key() @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == receipt.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == receipt.payer @ ErrorCode::InvalidRefundAccount,
+        constraint = payer_ata.mint == receipt.pay_out_token @ ErrorCode::InvalidRefundAccount
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct CreateEscrowPayment<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = from_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        address = payment.pay_in_token @ ErrorCode::InvalidPayInMint,
+        constraint = payment.pay_out_token == payment.pay_in_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Escrow::INIT_SPACE,
+        seeds = [ESCROW_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(
+        init,
+        payer = payer,
+        token::mint = mint,
+        token::authority = escrow,
+        token::token_program = token_program,
+        seeds = [ESCROW_VAULT_SEED, escrow.key().as_ref()],
+        bump
+    )]
+    pub vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: String)]
+pub struct ReleaseEscrow<'info> {
+    #[account(
+        constraint = authority.key() == escrow.merchant || authority.key() == config.admin @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+    /// CHECK: receives the escrow rent
+    #[account(mut, address = escrow.payer)]
+    pub payer: AccountInfo<'info>,
+    #[account(
+        mut,
+        close = payer,
+        seeds = [ESCROW_SEED, escrow.merchant.as_ref(), &order_seed(&order_id)],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut, seeds = [ESCROW_VAULT_SEED, escrow.key().as_ref()], bump)]
+    pub vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = escrow.mint @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == escrow.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == escrow.mint @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == escrow.mint @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        seeds = [RECEIPT_SEED, escrow.merchant.as_ref(), &order_seed(&order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(order_id: String)]
+pub struct RefundEscrow<'info> {
+    /// CHECK: receives the escrow rent
+    #[account(mut, address = escrow.payer)]
+    pub payer: AccountInfo<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == escrow.payer @ ErrorCode::InvalidRefundAccount,
+        constraint = payer_ata.mint == escrow.mint @ ErrorCode::InvalidRefundAccount
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        close = payer,
+        seeds = [ESCROW_SEED, escrow.merchant.as_ref(), &order_seed(&order_id)],
+        bump = escrow.bump
+    )]
+    pub escrow: Account<'info, Escrow>,
+    #[account(mut, seeds = [ESCROW_VAULT_SEED, escrow.key().as_ref()], bump)]
+    pub vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = escrow.mint @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+/// Amount `token_account` delegates to `delegate`, failing if it delegates to another account.
+fn delegated_amount(
+    token_account: &InterfaceAccount<token_interface::TokenAccount>,
+    delegate: &Pubkey,
+) -> Result<u64> {
+    match token_account.delegate {
+        COption::None => Ok(0),
+        COption::Some(current) if current == *delegate => Ok(token_account.delegated_amount),
+        COption::Some(_) => err!(ErrorCode::DelegateInUse),
+    }
+}
+
+fn approve_subscription<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    payer_ata: &InterfaceAccount<'info, token_interface::TokenAccount>,
+    payer: &Signer<'info>,
+    subscription: &Account<'info, Subscription>,
+    periods: u32,
+) -> Result<u64> {
+    // Ensure the approval does not replace the delegate of spending authorizations
+    delegated_amount(payer_ata, &subscription.key())?;
+
+    let allowance = subscription
+        .charge_amount()?
+        .checked_mul(periods as u64)
+        .ok_or(ErrorCode::SubscriptionAllowanceOverflow)?;
+    token_interface::approve(
+        CpiContext::new(
+            token_program.to_account_info(),
+            token_interface::Approve {
+                to: payer_ata.to_account_info(),
+                delegate: subscription.to_account_info(),
+                authority: payer.to_account_info(),
+            },
+        ),
+        allowance,
+    )?;
+
+    Ok(allowance)
+}
+
+#[derive(Accounts)]
+#[instruction(terms: SubscriptionTerms)]
+pub struct CreateSubscription<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == payer.key() @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == terms.mint @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = terms.mint @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Subscription::INIT_SPACE,
+        seeds = [SUBSCRIPTION_SEED, payer.key().as_ref(), terms.merchant.as_ref(), &order_seed(&terms.plan_id)],
+        bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: String)]
+pub struct ChargeSubscription<'info> {
+    #[account(
+        mut,
+        seeds = [
+            SUBSCRIPTION_SEED,
+            subscription.payer.as_ref(),
+            subscription.merchant.as_ref(),
+            &order_seed(&plan_id)
+        ],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == subscription.payer @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == subscription.mint @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(address = subscription.mint @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == subscription.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == subscription.mint @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == subscription.mint @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: String)]
+pub struct ManageSubscription<'info> {
+    #[account(
+        constraint = authority.key() == subscription.payer || authority.key() == subscription.merchant @ ErrorCode::Unauthorized
+    )]
+    pub authority: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            SUBSCRIPTION_SEED,
+            subscription.payer.as_ref(),
+            subscription.merchant.as_ref(),
+            &order_seed(&plan_id)
+        ],
+        bump = subscription.bump
+    )]
+    pub subscription: Account<'info, Subscription>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: String)]
+pub struct ProposeSubscriptionPrice<'info> {
+    pub merchant: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_SEED, subscription.payer.as_ref(), merchant.key().as_ref(), &order_seed(&plan_id)],
+        bump = subscription.bump,
+        has_one = merchant @ ErrorCode::Unauthorized
+    )]
+    pub subscription: Account<'info, Subscription>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: String)]
+pub struct AcceptSubscriptionPrice<'info> {
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == payer.key() @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == subscription.mint @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        seeds = [SUBSCRIPTION_SEED, payer.key().as_ref(), subscription.merchant.as_ref(), &order_seed(&plan_id)],
+        bump = subscription.bump,
+        has_one = payer @ ErrorCode::Unauthorized
+    )]
+    pub subscription: Account<'info, Subscription>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(plan_id: String)]
+pub struct CancelSubscription<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == payer.key() @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == subscription.mint @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        close = payer,
+        seeds = [SUBSCRIPTION_SEED, payer.key().as_ref(), subscription.merchant.as_ref(), &order_seed(&plan_id)],
+        bump = subscription.bump,
+        has_one = payer @ ErrorCode::Unauthorized
+    )]
+    pub subscription: Account<'info, Subscription>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(merchant: Pubkey)]
+pub struct CreateSpendingAuthorization<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == payer.key() @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == mint.key() @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + SpendingAuthorization::INIT_SPACE,
+        seeds = [SPENDING_AUTHORIZATION_SEED, payer.key().as_ref(), merchant.as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub authorization: Account<'info, SpendingAuthorization>,
+    /// CHECK: delegate of the payer's token account shared by its authorizations, holds no data
+    #[account(seeds = [SPENDING_DELEGATE_SEED, payer.key().as_ref(), mint.key().as_ref()], bump)]
+    pub delegate: UncheckedAccount<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(payment: Payment)]
+pub struct PullPayment<'info> {
+    #[account(mut, address = payment.merchant @ ErrorCode::InvalidMerchantAccount)]
+    pub merchant: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [
+            SPENDING_AUTHORIZATION_SEED,
+            authorization.payer.as_ref(),
+            merchant.key().as_ref(),
+            payment.pay_in_token.as_ref()
+        ],
+        bump = authorization.bump,
+        has_one = merchant @ ErrorCode::Unauthorized
+    )]
+    pub authorization: Account<'info, SpendingAuthorization>,
+    /// CHECK: delegate of the payer's token account shared by its authorizations, holds no data
+    #[account(
+        seeds = [SPENDING_DELEGATE_SEED, authorization.payer.as_ref(), payment.pay_in_token.as_ref()],
+        bump = authorization.delegate_bump
+    )]
+    pub delegate: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == authorization.payer @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == payment.pay_in_token @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        address = payment.pay_in_token @ ErrorCode::InvalidPayInMint,
+        constraint = payment.pay_out_token == payment.pay_in_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == payment.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == payment.pay_out_token @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == payment.pay_in_token @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = merchant,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, payment.merchant.as_ref(), &order_seed(&payment.order_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeSpendingAuthorization<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        constraint = payer_ata.owner == payer.key() @ ErrorCode::InvalidPayerAccount,
+        constraint = payer_ata.mint == authorization.mint @ ErrorCode::InvalidPayInMint
+    )]
+    pub payer_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(mut, close = payer, has_one = payer @ ErrorCode::Unauthorized)]
+    pub authorization: Account<'info, SpendingAuthorization>,
+    /// CHECK: delegate of the payer's token account shared by its authorizations, holds no data
+    #[account(
+        seeds = [SPENDING_DELEGATE_SEED, payer.key().as_ref(), authorization.mint.as_ref()],
+        bump = authorization.delegate_bump
+    )]
+    pub delegate: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+#[instruction(invoice_id: String)]
+pub struct CreateInvoice<'info> {
+    #[account(mut)]
+    pub merchant: Signer<'info>,
+    #[account(
+        init,
+        payer = merchant,
+        space = 8 + Invoice::INIT_SPACE,
+        seeds = [INVOICE_SEED, merchant.key().as_ref(), &order_seed(&invoice_id)],
+        bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(invoice_id: String)]
+pub struct PayInvoice<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [INVOICE_SEED, invoice.merchant.as_ref(), &order_seed(&invoice_id)],
+        bump = invoice.bump
+    )]
+    pub invoice: Account<'info, Invoice>,
+    #[account(constraint = invoice.amount(&mint.key()).is_some() @ ErrorCode::InvalidPayInMint)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = from_ata.mint == mint.key() @ ErrorCode::InvalidPayInMint)]
+    pub from_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = merchant_ata.owner == invoice.merchant @ ErrorCode::InvalidMerchantAccount,
+        constraint = merchant_ata.mint == mint.key() @ ErrorCode::InvalidPayOutMint
+    )]
+    pub merchant_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_ata.owner == config.treasury_owner @ ErrorCode::InvalidTreasuryAccount,
+        constraint = treasury_ata.mint == mint.key() @ ErrorCode::InvalidTreasuryAccount
+    )]
+    pub treasury_ata: InterfaceAccount<'info, token_interface::TokenAccount>,
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + OrderReceipt::INIT_SPACE,
+        seeds = [RECEIPT_SEED, invoice.merchant.as_ref(), &order_seed(&invoice_id)],
+        bump
+    )]
+    pub receipt: Account<'info, OrderReceipt>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// CHECK: instructions sysvar
+    #[account(address = sysvar_instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [CONFIG_SEED],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::Paydefi>,
+    #[account(constraint = program_data.upgrade_authority_address == Some(admin.key()) @ ErrorCode::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(mut, seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::Unauthorized)]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+#[instruction(program_id: Pubkey)]
+pub struct AddSwapProgram<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AllowedSwapProgram::INIT_SPACE,
+        seeds = [SWAP_PROGRAM_SEED, program_id.as_ref()],
+        bump
+    )]
+    pub allowed_swap_program: Account<'info, AllowedSwapProgram>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveSwapProgram<'info> {
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(seeds = [CONFIG_SEED], bump = config.bump, has_one = admin @ ErrorCode::Unauthorized)]
+    pub config: Account<'info, Config>,
+    #[account(mut, close = admin)]
+    pub allowed_swap_program: Account<'info, AllowedSwapProgram>,
+}
+
+/// Program wide settings, managed by the admin.
+#[account]
+#[derive(InitSpace)]
+pub struct Config {
+    pub admin: Pubkey,
+    /// Wallet that owns the token accounts receiving payment fees
+    pub treasury_owner: Pubkey,
+    /// Key that signs payment quotes issued by the backend
+    pub quote_signer: Pubkey,
+    pub paused: bool,
+    pub bump: u8,
+}
+
+/// Marks a program that routed swap payments may invoke.
+#[account]
+#[derive(InitSpace)]
+pub struct AllowedSwapProgram {
+    pub program_id: Pubkey,
+    pub bump: u8,
+}
+
+/// Settlement record of an order, one per merchant and order id.
+#[account]
+#[derive(InitSpace)]
+pub struct OrderReceipt {
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+    pub pay_in_token: Pubkey,
+    pub pay_out_token: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub slot: u64,
+    pub bump: u8,
+    /// Total pay_out_token returned to the payer by the merchant
+    pub refunded_amount: u64,
+}
+
+/// Funds of an order held until the merchant releases them or the payer refunds them.
+#[account]
+#[derive(InitSpace)]
+pub struct Escrow {
+    pub payer: Pubkey,
+    pub merchant: Pubkey,
+    pub mint: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    /// Deadline for the release, the payer may refund afterwards
+    pub release_deadline: i64,
+    pub bump: u8,
+}
+
+impl Escrow {
+    /// The escrowed payment, as settled on release.
+    pub fn payment(&self, order_id: String) -> Payment {
+        Payment {
+            order_id,
+            pay_in_token: self.mint,
+            pay_out_token: self.mint,
+            pay_in_amount: self.pay_in_amount,
+            pay_out_amount: self.pay_out_amount,
+            merchant: self.merchant,
+            expiry: self.release_deadline,
+            gross_up_transfer_fee: false,
+        }
+    }
+}
+
+/// Recurring charge of a payer by a merchant, delegated the payer's token account.
+#[account]
+#[derive(InitSpace)]
+pub struct Subscription {
+    pub payer: Pubkey,
+    pub merchant: Pubkey,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub period: i64,
+    /// Unix timestamp from which the next period can be charged
+    pub next_due: i64,
+    /// Unix timestamp the subscription was paused at, zero while active
+    pub paused_at: i64,
+    /// Party that paused the subscription, default while active
+    pub paused_by: Pubkey,
+    /// Amount proposed by the merchant, awaiting the payer's acceptance
+    pub pending_amount: Option<u64>,
+    pub bump: u8,
+}
+
+impl Subscription {
+    pub fn is_paused(&self) -> bool {
+        self.paused_at != 0
+    }
+
+    /// Amount pulled from the payer each period, merchant amount plus fee.
+    pub fn charge_amount(&self) -> Result<u64> {
+        self.amount
+            .checked_add(self.fee)
+            .ok_or_else(|| ErrorCode::SubscriptionAllowanceOverflow.into())
+    }
+}
+
+/// Standing permission for a merchant to pull up to `cap` per period from a payer.
+#[account]
+#[derive(InitSpace)]
+pub struct SpendingAuthorization {
+    pub payer: Pubkey,
+    pub merchant: Pubkey,
+    pub mint: Pubkey,
+    pub cap: u64,
+    pub period: i64,
+    /// Unix timestamp the current period started at
+    pub period_start: i64,
+    /// Amount pulled in the current period
+    pub spent: u64,
+    /// Part of the delegated amount still reserved for this authorization
+    pub allowance: u64,
+    pub bump: u8,
+    pub delegate_bump: u8,
+}
+
+impl SpendingAuthorization {
+    /// Moves on to the period containing `now`, resetting the spent amount.
+    pub fn roll_period(&mut self, now: i64) {
+        let elapsed_periods = (now - self.period_start) / self.period;
+        if elapsed_periods > 0 {
+            self.period_start += elapsed_periods * self.period;
+            self.spent = 0;
+        }
+    }
+}
+
+/// Amount requested by a merchant, payable once before expiry.
+#[account]
+#[derive(InitSpace)]
+pub struct Invoice {
+    pub merchant: Pubkey,
+    /// Amount the merchant receives in each accepted mint
+    #[max_len(MAX_INVOICE_MINTS)]
+    pub amounts: Vec<InvoiceAmount>,
+    pub expiry: i64,
+    /// Hash of the off-chain invoice memo
+    pub memo_hash: [u8; 32],
+    pub payer: Pubkey,
+    pub paid_slot: u64,
+    pub bump: u8,
+}
+
+impl Invoice {
+    pub fn is_paid(&self) -> bool {
+        self.paid_slot != 0
+    }
+
+    /// Amount the merchant receives when paid in `mint`, if the mint is accepted.
+    pub fn amount(&self, mint: &Pubkey) -> Option<u64> {
+        self.amounts
+            .iter()
+            .find(|invoice_amount| invoice_amount.mint == *mint)
+            .map(|invoice_amount| invoice_amount.amount)
+    }
+
+    /// The payment settling this invoice in `mint` with `fee_amount` on top.
+    pub fn payment(&self, invoice_id: String, mint: Pubkey, fee_amount: u64) -> Result<Payment> {
+        let amount = self.amount(&mint).ok_or(ErrorCode::InvalidPayInMint)?;
+        let pay_in_amount = amount
+            .checked_add(fee_amount)
+            .ok_or(ErrorCode::InvoiceAmountOverflow)?;
+        Ok(Payment {
+            order_id: invoice_id,
+            pay_in_token: mint,
+            pay_out_token: mint,
+            pay_in_amount,
+            pay_out_amount: amount,
+            merchant: self.merchant,
+            expiry: self.expiry,
+            gross_up_transfer_fee: false,
+        })
+    }
+}
+
+impl OrderReceipt {
+    pub fn is_settled(&self) -> bool {
+        self.slot != 0
+    }
+
+    /// Records `payment` as settled, `pay_in_amount` being what the payer actually spent.
+    pub fn settle(
+        &mut self,
+        payment: &Payment,
+        pay_in_amount: u64,
+        payer: Pubkey,
+        fee_collected: u64,
+        bump: u8,
+    ) -> Result<()> {
+        self.merchant = payment.merchant;
+        self.payer = payer;
+        self.pay_in_token = payment.pay_in_token;
+        self.pay_out_token = payment.pay_out_token;
+        self.pay_in_amount = pay_in_amount;
+        self.pay_out_amount = payment.pay_out_amount;
+        self.fee_collected = fee_collected;
+        self.slot = Clock::get()?.slot;
+        self.bump = bump;
+        Ok(())
+    }
+}
+
+/// Reads the transfer fee extension of a Token-2022 mint, if it has one.
+fn transfer_fee_config(mint: &InterfaceAccount<Mint>) -> Result<Option<TransferFeeConfig>> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != token_2022::ID {
+        return Ok(None);
+    }
+
+    let data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<MintState>::unpack(&data)?;
+    Ok(mint_state.get_extension::<TransferFeeConfig>().ok().copied())
+}
+
+/// Fee withheld by the mint when `amount` is transferred.
+fn transfer_fee(fee_config: Option<&TransferFeeConfig>, amount: u64) -> Result<u64> {
+    match fee_config {
+        Some(fee_config) => fee_config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(error!(ErrorCode::TransferFeeOverflow)),
+        None => Ok(0),
+    }
+}
+
+/// Fee that has to be added to a transfer so that `amount` arrives after the mint's cut.
+fn inverse_transfer_fee(fee_config: Option<&TransferFeeConfig>, amount: u64) -> Result<u64> {
+    match fee_config {
+        Some(fee_config) => fee_config
+            .calculate_inverse_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(error!(ErrorCode::TransferFeeOverflow)),
+        None => Ok(0),
+    }
+}
+
+/// Splits `total_fee` by percentages that sum up to `FEE_DENOMINATOR`.
+///
+/// Shares are rounded down and the leftover units go one each to the receivers with the
+/// largest remainders, earlier receivers first on ties, so the shares always sum to `total_fee`.
+fn split_fee(total_fee: u64, percentages: &[u32]) -> Vec<u64> {
+    let mut shares = Vec::with_capacity(percentages.len());
+    let mut remainders = Vec::with_capacity(percentages.len());
+    for (i, percentage) in percentages.iter().enumerate() {
+        let scaled = total_fee as u128 * *percentage as u128;
+        shares.push((scaled / FEE_DENOMINATOR as u128) as u64);
+        remainders.push((scaled % FEE_DENOMINATOR as u128, i));
+    }
+
+    let mut dust = total_fee.saturating_sub(shares.iter().sum());
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for (_, i) in remainders {
+        if dust == 0 {
+            break;
+        }
+        shares[i] += 1;
+        dust -= 1;
+    }
+
+    shares
+}
+
+/// Splits `total_fee` across fee shares, which must sum up to 100% when there is a fee.
+fn split_fee_shares(total_fee: u64, fee_shares: &[FeeShare]) -> Result<Vec<u64>> {
+    let percentages: Vec<u32> = fee_shares.iter().map(|share| share.bps as u32).collect();
+    if total_fee > 0 {
+        let total_percentage: u32 = percentages.iter().sum();
+        require!(total_percentage == FEE_DENOMINATOR, ErrorCode::InvalidPercentage);
+    }
+
+    Ok(split_fee(total_fee, &percentages))
+}
+
+/// Quote message of a payment whose fee is split by `percentages` across the `receivers` token accounts.
+fn fee_percentages_message(payment: &Payment, percentages: &[u32; 8], receivers: &[Pubkey; 8]) -> Result<Vec<u8>> {
+    Ok((payment, percentages, receivers).try_to_vec()?)
+}
+
+/// Quote message of a payment whose fee is split by `fee_shares`, binding the receivers.
+fn fee_shares_message(payment: &Payment, fee_shares: &[FeeShare]) -> Result<Vec<u8>> {
+    Ok((payment, fee_shares).try_to_vec()?)
+}
+
+/// Order ids can exceed the 32 byte seed limit, so receipts are keyed by their hash.
+pub fn order_seed(order_id: &str) -> [u8; 32] {
+    hash(order_id.as_bytes()).to_bytes()
+}
+
+#[event]
+pub struct PaymentCompleted {
+    pub order_id: String,
+    pub pay_in_token: Pubkey,
+    pub pay_out_token: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub transfer_fee_withheld: u64,
+    pub treasury: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct PaymentCompletedAndFeeDistributed {
+    pub order_id: String,
+    pub pay_in_token: Pubkey,
     pub pay_out_token: Pubkey,
     pub pay_in_amount: u64,
     pub pay_out_amount: u64,
     pub fee_collected: u64,
+    pub transfer_fee_withheld: u64,
     pub fee_receiver1: Pubkey,
     pub fee_receiver2: Pubkey,
     pub fee_receiver3: Pubkey,
@@ -455,6 +4175,26 @@ pub struct PaymentCompletedAndFeeDistributed {
     pub payer: Pubkey,
 }
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct FeeShareReceived {
+    pub receiver: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct PaymentCompletedAndFeeShared {
+    pub order_id: String,
+    pub pay_in_token: Pubkey,
+    pub pay_out_token: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub transfer_fee_withheld: u64,
+    pub fee_shares: Vec<FeeShareReceived>,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
 #[event]
 pub struct SwapPaymentCompleted {
     pub order_id: String,
@@ -468,6 +4208,178 @@ pub struct SwapPaymentCompleted {
     pub payer: Pubkey,
 }
 
+#[event]
+pub struct SwapPaymentCompletedAndFeeShared {
+    pub order_id: String,
+    pub pay_in_token: Pubkey,
+    pub pay_out_token: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub fee_shares: Vec<FeeShareReceived>,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct PaymentRefunded {
+    pub order_id: String,
+    pub token: Pubkey,
+    pub amount: u64,
+    pub refunded_amount: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct EscrowCreated {
+    pub order_id: String,
+    pub mint: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub release_deadline: i64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct EscrowRefunded {
+    pub order_id: String,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionCreated {
+    pub plan_id: String,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+    pub period: i64,
+    pub trial_period: i64,
+    pub next_due: i64,
+    pub allowance: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionCharged {
+    pub plan_id: String,
+    pub mint: Pubkey,
+    pub amount: u64,
+    pub fee_collected: u64,
+    pub next_due: i64,
+    pub treasury: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionPaused {
+    pub plan_id: String,
+    pub paused_at: i64,
+    pub authority: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionResumed {
+    pub plan_id: String,
+    pub next_due: i64,
+    pub authority: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionPriceProposed {
+    pub plan_id: String,
+    pub amount: u64,
+    pub pending_amount: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionPriceAccepted {
+    pub plan_id: String,
+    pub amount: u64,
+    pub allowance: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SubscriptionCancelled {
+    pub plan_id: String,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SpendingAuthorized {
+    pub mint: Pubkey,
+    pub cap: u64,
+    pub period: i64,
+    pub period_start: i64,
+    pub allowance: u64,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct PaymentPulled {
+    pub order_id: String,
+    pub mint: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub spent: u64,
+    pub period_start: i64,
+    pub treasury: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct SpendingAuthorizationRevoked {
+    pub mint: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct InvoiceCreated {
+    pub invoice_id: String,
+    pub amounts: Vec<InvoiceAmount>,
+    pub expiry: i64,
+    pub memo_hash: [u8; 32],
+    pub merchant: Pubkey,
+}
+
+#[event]
+pub struct InvoicePaid {
+    pub invoice_id: String,
+    pub memo_hash: [u8; 32],
+    pub mint: Pubkey,
+    pub pay_in_amount: u64,
+    pub pay_out_amount: u64,
+    pub fee_collected: u64,
+    pub treasury: Pubkey,
+    pub merchant: Pubkey,
+    pub payer: Pubkey,
+}
+
+#[event]
+pub struct PauseToggled {
+    pub paused: bool,
+    pub admin: Pubkey,
+}
+
 #[error_code]
 pub enum ErrorCode {
     #[msg("The payment has expired.")]
@@ -478,4 +4390,203 @@ pub enum ErrorCode {
     FeePercentageOverflow,
     #[msg("Invalid percentage, must sum to 100 or less.")]
     InvalidPercentage,
+    #[msg("The order has already been completed.")]
+    OrderAlreadyCompleted,
+    #[msg("The swap output does not cover the pay out amount.")]
+    InsufficientSwapOutput,
+    #[msg("The signer is not authorized.")]
+    Unauthorized,
+    #[msg("The payment quote signature is missing.")]
+    MissingQuoteSignature,
+    #[msg("The payment quote signature is invalid.")]
+    InvalidQuoteSignature,
+    #[msg("The source token account does not hold the pay in token.")]
+    InvalidPayInMint,
+    #[msg("The token account does not hold the pay out token.")]
+    InvalidPayOutMint,
+    #[msg("The token account does not belong to the merchant.")]
+    InvalidMerchantAccount,
+    #[msg("The treasury token account is invalid.")]
+    InvalidTreasuryAccount,
+    #[msg("The swap destination does not belong to the payer.")]
+    InvalidSwapDestination,
+    #[msg("The program is paused.")]
+    ProgramPaused,
+    #[msg("The mint charges a transfer fee and the payment does not allow it.")]
+    TransferFeeNotAllowed,
+    #[msg("Transfer fee calculation overflowed.")]
+    TransferFeeOverflow,
+    #[msg("The fee receiver accounts do not match the fee shares.")]
+    InvalidFeeShareCount,
+    #[msg("The fee receiver token account does not match its share.")]
+    InvalidFeeReceiver,
+    #[msg("The swap program is not allowed.")]
+    SwapProgramNotAllowed,
+    #[msg("The swap spent more than the pay in amount.")]
+    ExcessiveSwapInput,
+    #[msg("The Raydium pool state does not match the pool accounts.")]
+    InvalidAmmPool,
+    #[msg("The escrowed amount is below the merchant amount.")]
+    InvalidEscrowAmount,
+    #[msg("The escrow has not expired yet.")]
+    EscrowNotExpired,
+    #[msg("The refund token account does not belong to the payer.")]
+    InvalidRefundAccount,
+    #[msg("The escrow release deadline is before the payment expiry.")]
+    InvalidReleaseDeadline,
+    #[msg("The order has not been completed.")]
+    OrderNotCompleted,
+    #[msg("The refunds exceed the amount paid to the merchant.")]
+    RefundExceedsPayment,
+    #[msg("The token account does not belong to the payer.")]
+    InvalidPayerAccount,
+    #[msg("The subscription period must be positive.")]
+    InvalidSubscriptionPeriod,
+    #[msg("The subscription allowance overflowed.")]
+    SubscriptionAllowanceOverflow,
+    #[msg("The subscription is not due yet.")]
+    SubscriptionNotDue,
+    #[msg("The subscription is paused.")]
+    SubscriptionPaused,
+    #[msg("The subscription is not paused.")]
+    SubscriptionNotPaused,
+    #[msg("The amount does not match the proposed subscription price.")]
+    SubscriptionPriceMismatch,
+    #[msg("The pulled amount is below the merchant amount.")]
+    InvalidPullAmount,
+    #[msg("The pull exceeds the spending cap of the current period.")]
+    SpendingCapExceeded,
+    #[msg("The spending period must be positive.")]
+    InvalidSpendingPeriod,
+    #[msg("The spending allowance overflowed.")]
+    SpendingAllowanceOverflow,
+    #[msg("The token account is already delegated to another account.")]
+    DelegateInUse,
+    #[msg("The spending authorization has used up its approved allowance.")]
+    SpendingAllowanceExhausted,
+    #[msg("The invoice must accept between one and eight distinct mints.")]
+    InvalidInvoiceMints,
+    #[msg("The invoice amount plus fee overflowed.")]
+    InvoiceAmountOverflow,
+    #[msg("The pay out amount exceeds the pay in amount.")]
+    InvalidPaymentAmounts,
+    #[msg("The pay out amount plus fee overflowed.")]
+    SwapAmountOverflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_program::{ed25519_program, instruction::Instruction};
+
+    /// Ed25519 precompile instruction carrying `signer` and `message` inline.
+    fn ed25519_instruction(signer: &Pubkey, message: &[u8]) -> Instruction {
+        let pubkey_offset: u16 = 16;
+        let signature_offset = pubkey_offset + 32;
+        let message_offset = signature_offset + 64;
+        let mut data = vec![1, 0];
+        for value in [
+            signature_offset,
+            u16::MAX,
+            pubkey_offset,
+            u16::MAX,
+            message_offset,
+            message.len() as u16,
+            u16::MAX,
+        ] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(signer.as_ref());
+        data.extend_from_slice(&[0; 64]);
+        data.extend_from_slice(message);
+        Instruction {
+            program_id: ed25519_program::ID,
+            accounts: vec![],
+            data,
+        }
+    }
+
+    fn payment() -> Payment {
+        Payment {
+            order_id: "order-1".to_string(),
+            pay_in_token: Pubkey::new_unique(),
+            pay_out_token: Pubkey::new_unique(),
+            pay_in_amount: 1_010_000,
+            pay_out_amount: 1_000_000,
+            merchant: Pubkey::new_unique(),
+            expiry: 1_700_000_000,
+            gross_up_transfer_fee: false,
+        }
+    }
+
+    #[test]
+    fn quote_rejects_self_directed_fee_share() {
+        let quote_signer = Pubkey::new_unique();
+        let payment = payment();
+        let quoted = vec![FeeShare {
+            receiver: Pubkey::new_unique(),
+            bps: 10_000,
+        }];
+        let quoted_message = fee_shares_message(&payment, &quoted).unwrap();
+        let ix = ed25519_instruction(&quote_signer, &quoted_message);
+        assert!(ed25519::verify_instruction(&ix, &quote_signer, &quoted_message).is_ok());
+
+        // The payer swaps in their own wallet as the only receiver
+        let self_directed = vec![FeeShare {
+            receiver: Pubkey::new_unique(),
+            bps: 10_000,
+        }];
+        let submitted_message = fee_shares_message(&payment, &self_directed).unwrap();
+        assert_eq!(
+            ed25519::verify_instruction(&ix, &quote_signer, &submitted_message),
+            Err(ErrorCode::InvalidQuoteSignature.into())
+        );
+    }
+
+    #[test]
+    fn quote_rejects_redirected_fee_percentages() {
+        let quote_signer = Pubkey::new_unique();
+        let payment = payment();
+        let treasury_ata = Pubkey::new_unique();
+        let receivers = [treasury_ata; 8];
+        let percentages = [10_000, 0, 0, 0, 0, 0, 0, 0];
+        let quoted_message = fee_percentages_message(&payment, &percentages, &receivers).unwrap();
+        let ix = ed25519_instruction(&quote_signer, &quoted_message);
+        assert!(ed25519::verify_instruction(&ix, &quote_signer, &quoted_message).is_ok());
+
+        // The payer sends the whole fee to their own token account instead
+        let mut redirected = receivers;
+        redirected[1] = Pubkey::new_unique();
+        let submitted_message =
+            fee_percentages_message(&payment, &[0, 10_000, 0, 0, 0, 0, 0, 0], &redirected).unwrap();
+        assert_eq!(
+            ed25519::verify_instruction(&ix, &quote_signer, &submitted_message),
+            Err(ErrorCode::InvalidQuoteSignature.into())
+        );
+    }
+
+    #[test]
+    fn split_fee_sums_to_total_fee() {
+        let shares = split_fee(1_001, &[3_333, 3_333, 3_334]);
+        assert_eq!(shares.iter().sum::<u64>(), 1_001);
+        // The dust goes to the largest remainders first
+        assert_eq!(shares, vec![334, 333, 334]);
+    }
+
+    #[test]
+    fn split_fee_breaks_ties_by_index() {
+        assert_eq!(split_fee(1, &[5_000, 5_000]), vec![1, 0]);
+        assert_eq!(split_fee(3, &[2_500, 2_500, 2_500, 2_500]), vec![1, 1, 1, 0]);
+    }
+
+    #[test]
+    fn split_fee_of_zero_fee() {
+        assert_eq!(split_fee(0, &[6_000, 4_000]), vec![0, 0]);
+        // Without a fee the shares need not add up to 100%
+        let fee_shares = [FeeShare {
+            receiver: Pubkey::new_unique(),
+            bps: 1_000,
+        }];
+        assert_eq!(split_fee_shares(0, &fee_shares).unwrap(), vec![0]);
+    }
 }
@@ -0,0 +1,143 @@
+//! Instruction types for the Orca Whirlpool program
+
+#![allow(clippy::too_many_arguments)]
+
+use anchor_spl::token::spl_token;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::convert::TryInto;
+
+solana_program::declare_id!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+
+/// Anchor discriminator of `swap`, `sha256("global:swap")[..8]`.
+pub const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+
+/// Lowest sqrt price a pool can reach, the limit for an unbounded a to b swap.
+pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
+/// Highest sqrt price a pool can reach, the limit for an unbounded b to a swap.
+pub const MAX_SQRT_PRICE_X64: u128 = 79226673515401279992447579055;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapInstruction {
+    /// Input amount when `amount_specified_is_input`, output amount otherwise
+    pub amount: u64,
+    /// Minimum output or maximum input depending on `amount_specified_is_input`
+    pub other_amount_threshold: u64,
+    /// Price limit of the swap as a Q64.64 square root
+    pub sqrt_price_limit: u128,
+    pub amount_specified_is_input: bool,
+    /// Swap token A for token B, or B for A when false
+    pub a_to_b: bool,
+}
+
+impl SwapInstruction {
+    /// Unpacks a byte buffer into a [SwapInstruction](struct.SwapInstruction.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != 42 || input[..8] != SWAP_DISCRIMINATOR {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let amount = u64::from_le_bytes(input[8..16].try_into().unwrap());
+        let other_amount_threshold = u64::from_le_bytes(input[16..24].try_into().unwrap());
+        let sqrt_price_limit = u128::from_le_bytes(input[24..40].try_into().unwrap());
+        let amount_specified_is_input = Self::unpack_bool(input[40])?;
+        let a_to_b = Self::unpack_bool(input[41])?;
+        Ok(Self {
+            amount,
+            other_amount_threshold,
+            sqrt_price_limit,
+            amount_specified_is_input,
+            a_to_b,
+        })
+    }
+
+    fn unpack_bool(byte: u8) -> Result<bool, ProgramError> {
+        match byte {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ProgramError::InvalidInstructionData),
+        }
+    }
+
+    /// Packs a [SwapInstruction](struct.SwapInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(42);
+        buf.extend_from_slice(&SWAP_DISCRIMINATOR);
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.other_amount_threshold.to_le_bytes());
+        buf.extend_from_slice(&self.sqrt_price_limit.to_le_bytes());
+        buf.push(self.amount_specified_is_input as u8);
+        buf.push(self.a_to_b as u8);
+        buf
+    }
+}
+
+/// Creates a 'swap' instruction.
+///
+///   0. `[]` Spl Token program id
+///   1. `[signer]` User wallet Account
+///   2. `[writable]` Whirlpool Account
+///   3. `[writable]` User token A Account
+///   4. `[writable]` Whirlpool token A vault
+///   5. `[writable]` User token B Account
+///   6. `[writable]` Whirlpool token B vault
+///   7. `[writable]` Tick array 0, containing the current tick
+///   8. `[writable]` Tick array 1, next in the swap direction
+///   9. `[writable]` Tick array 2, next in the swap direction
+///   10. `[writable]` Oracle Account, derived from `find_program_address(&[b"oracle", whirlpool])`
+pub fn swap(
+    whirlpool_program: &Pubkey,
+    token_authority: &Pubkey,
+    whirlpool: &Pubkey,
+    token_owner_account_a: &Pubkey,
+    token_vault_a: &Pubkey,
+    token_owner_account_b: &Pubkey,
+    token_vault_b: &Pubkey,
+    tick_array_0: &Pubkey,
+    tick_array_1: &Pubkey,
+    tick_array_2: &Pubkey,
+    oracle: &Pubkey,
+
+    amount: u64,
+    other_amount_threshold: u64,
+    sqrt_price_limit: u128,
+    amount_specified_is_input: bool,
+    a_to_b: bool,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction {
+        amount,
+        other_amount_threshold,
+        sqrt_price_limit,
+        amount_specified_is_input,
+        a_to_b,
+    }
+    .pack();
+
+    let accounts = vec![
+        // spl token
+        AccountMeta::new_readonly(spl_token::id(), false),
+        // user
+        AccountMeta::new_readonly(*token_authority, true),
+        // whirlpool
+        AccountMeta::new(*whirlpool, false),
+        AccountMeta::new(*token_owner_account_a, false),
+        AccountMeta::new(*token_vault_a, false),
+        AccountMeta::new(*token_owner_account_b, false),
+        AccountMeta::new(*token_vault_b, false),
+        // ticks
+        AccountMeta::new(*tick_array_0, false),
+        AccountMeta::new(*tick_array_1, false),
+        AccountMeta::new(*tick_array_2, false),
+        // oracle
+        AccountMeta::new(*oracle, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *whirlpool_program,
+        accounts,
+        data,
+    })
+}